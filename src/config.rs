@@ -0,0 +1,536 @@
+//! Command line arguments and configuration files.
+//!
+//! A configuration file holds one setting per line, as `<key> = <value>`. Keys are the names of the long
+//! command line options without the leading dashes, and take the same values. Settings which may be given
+//! more than once, like `listen` or `zone`, add up. Empty lines and everything after a `#` are ignored.
+//! Relative paths are relative to the directory of the configuration file.
+//!
+//! ```text
+//! # Answer on all addresses, IPv4 and IPv6.
+//! listen = [::]:53
+//! pipeline = acl, log, blocklist, authoritative, cache, forward
+//! allow = 192.168.0.0/16
+//! log-queries = true
+//! zone = home.arpa=zones/home.arpa.zone
+//! blocklist = lists/ads.txt
+//! resolver = 9.9.9.9:53,149.112.112.112:53
+//! upstream-strategy = fastest
+//! cache-size = 50000
+//! ```
+//!
+//! Options given on the command line override those of the configuration file; for settings which may be
+//! given more than once, the values from the command line replace all values from the file.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use dns_starter_rust::message::{Name, DEFAULT_UDP_PAYLOAD, MIN_UDP_PAYLOAD};
+use dns_starter_rust::resolver::{BlockResponse, Network, RetryPolicy, Strategy, DEFAULT_MAX_ENTRIES};
+use dns_starter_rust::server::{DEFAULT_MAX_TCP_CONNECTIONS, DEFAULT_TCP_IDLE_TIMEOUT, DEFAULT_WORKERS};
+
+/// Address listened on for both UDP and TCP when none is configured.
+pub const DEFAULT_LISTEN: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 2053);
+
+pub const USAGE: &str = "\
+Usage: dns-starter-rust [OPTIONS]
+
+Options:
+  --config <file>                 Read settings from <file>, see below; other options override them
+  --check-config                  Validate the settings, load all zones and lists, then exit
+  --help                          Print this help
+
+Listeners:
+  --listen <addr>                 Answer UDP and TCP requests on <addr> (repeatable, default 127.0.0.1:2053);
+                                  [::]:<port> accepts IPv4 too on dual-stack systems, so 0.0.0.0:<port>
+                                  is skipped next to it; with net.ipv6.bindv6only = 1 on Linux, IPv4
+                                  requests then go unanswered, list specific IPv4 addresses instead
+  --listen-udp <addr>             Answer UDP requests on <addr> (repeatable)
+  --listen-tcp <addr>             Answer TCP requests on <addr> (repeatable)
+  --max-udp-payload <bytes>       Largest UDP response sent (default 1232)
+  --workers <n>                   Threads answering requests per socket and listener (default 32)
+  --max-tcp-connections <n>       TCP connections served at the same time (default 256)
+  --tcp-idle-timeout <ms>         Close idle TCP connections after <ms> (default 10000)
+
+Pipeline:
+  --pipeline <stages>             Comma separated stages questions pass through, in order (default
+                                  acl,log,hosts,blocklist,rewrite,authoritative,cache,forward);
+                                  stages without settings are skipped
+  --allow <network>               Only answer clients in <network>, e.g. 10.0.0.0/8 (repeatable)
+  --deny <network>                Refuse clients in <network> (repeatable)
+  --log-queries <bool>            Log every question to stderr
+  --hosts <file>                  Answer from a file in /etc/hosts format
+  --blocklist <file>              Block the domains listed in <file> (repeatable)
+  --allowlist <file>              Never block the domains listed in <file> (repeatable)
+  --block-response <response>     nxdomain, nodata, refused or sinkhole (default nxdomain)
+  --rewrite <from>=<to>           Resolve names below <from> as the same names below <to> (repeatable)
+  --zone <origin>=<file>          Serve the zone <origin> from a master file (repeatable)
+  --cache-size <n>                Responses kept in the cache, 0 disables it (default 10000)
+
+Upstreams:
+  --resolver <spec>               `recursive`, or comma separated upstream addresses to forward to
+  --route <domain>=<spec>         Resolve names below <domain> with <spec> (repeatable)
+  --root-hints <addrs>            Comma separated root server addresses for `recursive`
+  --upstream-timeout <ms>         Time to wait for each upstream response (default 1000)
+  --upstream-retries <n>          Attempts per upstream after the first (default 2)
+  --upstream-deadline <ms>        Time limit for resolving a question (default 10000)
+  --upstream-strategy <strategy>  failover, round-robin, random or fastest (default failover)
+  --upstream-tcp-reuse <bool>     Keep TCP connections to upstreams open
+
+Configuration files hold one `<option> = <value>` per line, with the option names above without the
+leading dashes, e.g. `listen = [::]:53`. Empty lines and everything after a `#` are ignored, and relative
+paths are relative to the directory of the file.
+";
+
+/// A stage of the resolver pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Refuses clients outside of `allow` or inside of `deny`.
+    Acl,
+    /// Logs every question with its outcome.
+    Log,
+    /// Answers from the `hosts` file.
+    Hosts,
+    /// Blocks the names of the `blocklists`.
+    Blocklist,
+    /// Applies the `rewrites`.
+    Rewrite,
+    /// Answers from the `zones`.
+    Authoritative,
+    /// Caches the responses of the later stages.
+    Cache,
+    /// Resolves with `resolver` and the `routes`.
+    Forward,
+}
+
+/// The stages in the order used unless `pipeline` is configured.
+pub const DEFAULT_PIPELINE: [Stage; 8] = [
+    Stage::Acl,
+    Stage::Log,
+    Stage::Hosts,
+    Stage::Blocklist,
+    Stage::Rewrite,
+    Stage::Authoritative,
+    Stage::Cache,
+    Stage::Forward,
+];
+
+impl FromStr for Stage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "acl" => Self::Acl,
+            "log" => Self::Log,
+            "hosts" => Self::Hosts,
+            "blocklist" => Self::Blocklist,
+            "rewrite" => Self::Rewrite,
+            "authoritative" => Self::Authoritative,
+            "cache" => Self::Cache,
+            "forward" => Self::Forward,
+            _ => bail!("Unknown pipeline stage '{s}'"),
+        })
+    }
+}
+
+/// What the command line asks for.
+pub enum Command {
+    /// Serve with the given settings.
+    Run(Options),
+    /// Validate the settings and exit.
+    CheckConfig(Options),
+    Help,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    /// Addresses to answer requests on. An IPv6 wildcard address like `[::]:53` also accepts IPv4 requests
+    /// on systems with dual-stack sockets, such as Linux by default; the IPv4 wildcard on the same port is dropped.
+    pub listen_udp: Vec<SocketAddr>,
+    pub listen_tcp: Vec<SocketAddr>,
+    pub resolver: Option<String>,
+    /// Domain suffixes with the resolver spec for them, in the format of `resolver`.
+    pub routes: Vec<(Name, String)>,
+    /// Zones served authoritatively, by origin and master file. They are not cached.
+    pub zones: Vec<(Name, PathBuf)>,
+    /// Domain lists whose names are blocked, unless they are on one of the allow lists.
+    pub blocklists: Vec<PathBuf>,
+    pub allowlists: Vec<PathBuf>,
+    pub block_response: BlockResponse,
+    /// File in the format of `/etc/hosts` whose entries are answered before any other resolver.
+    pub hosts: Option<PathBuf>,
+    /// Domain suffixes rewritten before resolving, from the first to the second name.
+    pub rewrites: Vec<(Name, Name)>,
+    /// Clients which may use the server; all if empty, except for those in `deny`.
+    pub allow: Vec<Network>,
+    pub deny: Vec<Network>,
+    pub log_queries: bool,
+    /// The stages questions pass through, in order.
+    pub pipeline: Vec<Stage>,
+    pub root_hints: Option<Vec<SocketAddr>>,
+    pub max_udp_payload: u16,
+    /// Number of threads answering requests, per UDP socket and per TCP listener.
+    pub workers: usize,
+    pub max_tcp_connections: usize,
+    pub tcp_idle_timeout: Duration,
+    pub cache_size: usize,
+    pub retry: RetryPolicy,
+    pub strategy: Strategy,
+    pub reuse_tcp: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            listen_udp: Vec::new(),
+            listen_tcp: Vec::new(),
+            resolver: None,
+            routes: Vec::new(),
+            zones: Vec::new(),
+            blocklists: Vec::new(),
+            allowlists: Vec::new(),
+            block_response: BlockResponse::NxDomain,
+            hosts: None,
+            rewrites: Vec::new(),
+            allow: Vec::new(),
+            deny: Vec::new(),
+            log_queries: false,
+            pipeline: DEFAULT_PIPELINE.to_vec(),
+            root_hints: None,
+            max_udp_payload: DEFAULT_UDP_PAYLOAD,
+            workers: DEFAULT_WORKERS,
+            max_tcp_connections: DEFAULT_MAX_TCP_CONNECTIONS,
+            tcp_idle_timeout: DEFAULT_TCP_IDLE_TIMEOUT,
+            cache_size: DEFAULT_MAX_ENTRIES,
+            retry: RetryPolicy::default(),
+            strategy: Strategy::Failover,
+            reuse_tcp: false,
+        }
+    }
+}
+
+impl Options {
+    /// Applies the setting `key` (an option name without dashes); relative paths are resolved against `base`.
+    fn set(&mut self, key: &str, value: &str, base: &Path) -> Result<()> {
+        let path = |value: &str| base.join(value);
+        match key {
+            "listen" => {
+                let addr = parse_listen(value, key)?;
+                self.listen_udp.push(addr);
+                self.listen_tcp.push(addr);
+            }
+            "listen-udp" => self.listen_udp.push(parse_listen(value, key)?),
+            "listen-tcp" => self.listen_tcp.push(parse_listen(value, key)?),
+            "resolver" => self.resolver = Some(value.to_owned()),
+            "route" => {
+                let (suffix, resolver) = value.split_once('=').context("Invalid 'route', expected '<domain>=<resolver>'")?;
+                let suffix = suffix.parse().context("Invalid domain in 'route'")?;
+                self.routes.push((suffix, resolver.to_owned()));
+            }
+            "zone" => {
+                let (origin, file) = value.split_once('=').context("Invalid 'zone', expected '<origin>=<file>'")?;
+                let origin = origin.parse().context("Invalid origin in 'zone'")?;
+                self.zones.push((origin, path(file)));
+            }
+            "blocklist" => self.blocklists.push(path(value)),
+            "allowlist" => self.allowlists.push(path(value)),
+            "block-response" => self.block_response = value.parse()?,
+            "hosts" => self.hosts = Some(path(value)),
+            "rewrite" => {
+                let (from, to) = value.split_once('=').context("Invalid 'rewrite', expected '<domain>=<domain>'")?;
+                let from = from.parse().context("Invalid domain in 'rewrite'")?;
+                let to = to.parse().context("Invalid domain in 'rewrite'")?;
+                self.rewrites.push((from, to));
+            }
+            "allow" => self.allow.push(value.parse()?),
+            "deny" => self.deny.push(value.parse()?),
+            "log-queries" => self.log_queries = value.parse().context("Invalid 'log-queries', expected true or false")?,
+            "pipeline" => self.pipeline = value.split(',').map(|stage| stage.trim().parse()).collect::<Result<_>>()?,
+            "root-hints" => {
+                let hints = value.split(',').map(|hint| hint.trim().parse()).collect::<Result<_, _>>();
+                self.root_hints = Some(hints.context("Invalid 'root-hints'")?);
+            }
+            "max-udp-payload" => {
+                self.max_udp_payload = value.parse::<u16>().context("Invalid 'max-udp-payload'")?.max(MIN_UDP_PAYLOAD)
+            }
+            "workers" => self.workers = value.parse().context("Invalid 'workers'")?,
+            "max-tcp-connections" => self.max_tcp_connections = value.parse().context("Invalid 'max-tcp-connections'")?,
+            "tcp-idle-timeout" => self.tcp_idle_timeout = parse_millis(value, key)?,
+            "cache-size" => self.cache_size = value.parse().context("Invalid 'cache-size'")?,
+            "upstream-timeout" => self.retry.attempt_timeout = parse_millis(value, key)?,
+            "upstream-retries" => self.retry.retries = value.parse().context("Invalid 'upstream-retries'")?,
+            "upstream-deadline" => self.retry.deadline = parse_millis(value, key)?,
+            "upstream-strategy" => self.strategy = value.parse()?,
+            "upstream-tcp-reuse" => self.reuse_tcp = value.parse().context("Invalid 'upstream-tcp-reuse', expected true or false")?,
+            _ => bail!("Unknown setting '{key}'"),
+        }
+        Ok(())
+    }
+
+    /// Replaces the fields set by `key` with those of `other`.
+    ///
+    /// For settings which may be given more than once, all values are replaced. `listen` sets the same
+    /// fields as `listen-udp` and `listen-tcp`, so whichever of them `other` got, it got all of their values.
+    fn take_setting(&mut self, other: &mut Options, key: &str) {
+        match key {
+            "listen" => {
+                self.listen_udp = other.listen_udp.clone();
+                self.listen_tcp = other.listen_tcp.clone();
+            }
+            "listen-udp" => self.listen_udp = other.listen_udp.clone(),
+            "listen-tcp" => self.listen_tcp = other.listen_tcp.clone(),
+            "resolver" => self.resolver = other.resolver.take(),
+            "route" => self.routes = mem::take(&mut other.routes),
+            "zone" => self.zones = mem::take(&mut other.zones),
+            "blocklist" => self.blocklists = mem::take(&mut other.blocklists),
+            "allowlist" => self.allowlists = mem::take(&mut other.allowlists),
+            "block-response" => self.block_response = other.block_response,
+            "hosts" => self.hosts = other.hosts.take(),
+            "rewrite" => self.rewrites = mem::take(&mut other.rewrites),
+            "allow" => self.allow = mem::take(&mut other.allow),
+            "deny" => self.deny = mem::take(&mut other.deny),
+            "log-queries" => self.log_queries = other.log_queries,
+            "pipeline" => self.pipeline = other.pipeline.clone(),
+            "root-hints" => self.root_hints = other.root_hints.take(),
+            "max-udp-payload" => self.max_udp_payload = other.max_udp_payload,
+            "workers" => self.workers = other.workers,
+            "max-tcp-connections" => self.max_tcp_connections = other.max_tcp_connections,
+            "tcp-idle-timeout" => self.tcp_idle_timeout = other.tcp_idle_timeout,
+            "cache-size" => self.cache_size = other.cache_size,
+            "upstream-timeout" => self.retry.attempt_timeout = other.retry.attempt_timeout,
+            "upstream-retries" => self.retry.retries = other.retry.retries,
+            "upstream-deadline" => self.retry.deadline = other.retry.deadline,
+            "upstream-strategy" => self.strategy = other.strategy,
+            "upstream-tcp-reuse" => self.reuse_tcp = other.reuse_tcp,
+            _ => {}
+        }
+    }
+
+    /// Applies the settings of the configuration file at `path`.
+    fn load(&mut self, path: &Path) -> Result<()> {
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read configuration file {}", path.display()))?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split_once('#').map_or(line, |(content, _)| content).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let context = || format!("{}:{}", path.display(), number + 1);
+            let (key, value) = line.split_once('=').with_context(|| format!("{}: expected '<key> = <value>'", context()))?;
+            self.set(key.trim(), value.trim(), base).with_context(context)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the command line arguments `args`, without the path of the executable.
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command> {
+    let mut args = args.into_iter();
+
+    // Options are pairs of a key and a value, given either as `--key value` or as `--key=value`.
+    let mut pairs = Vec::new();
+    let mut check_config = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" | "-h" => return Ok(Command::Help),
+            "--check-config" => check_config = true,
+            _ => {
+                let Some(option) = arg.strip_prefix("--") else {
+                    bail!("Unexpected argument '{arg}', see --help");
+                };
+                let (key, value) = match option.split_once('=') {
+                    Some((key, value)) => (key.to_owned(), value.to_owned()),
+                    None => (option.to_owned(), args.next().with_context(|| format!("Missing value for '{arg}'"))?),
+                };
+                pairs.push((key, value));
+            }
+        }
+    }
+
+    // The configuration file comes first, wherever it was given, so that the other options override it.
+    let mut options = Options::default();
+    for (_, path) in pairs.iter().filter(|(key, _)| key == "config") {
+        options.load(Path::new(path))?;
+    }
+    // The other options are collected separately, then replace the settings of the file they were given for.
+    let mut overrides = Options::default();
+    let mut keys = Vec::new();
+    for (key, value) in pairs.iter().filter(|(key, _)| key != "config") {
+        overrides.set(key, value, Path::new("")).with_context(|| format!("Invalid option '--{key}'"))?;
+        if !keys.contains(&key.as_str()) {
+            keys.push(key.as_str());
+        }
+    }
+    for key in keys {
+        options.take_setting(&mut overrides, key);
+    }
+
+    if options.listen_udp.is_empty() && options.listen_tcp.is_empty() {
+        options.listen_udp.push(DEFAULT_LISTEN);
+        options.listen_tcp.push(DEFAULT_LISTEN);
+    }
+    drop_covered_wildcards(&mut options.listen_udp, "UDP");
+    drop_covered_wildcards(&mut options.listen_tcp, "TCP");
+    Ok(if check_config { Command::CheckConfig(options) } else { Command::Run(options) })
+}
+
+/// Removes IPv4 wildcard addresses whose port is also listened on with the IPv6 wildcard address.
+///
+/// The IPv6 socket is dual-stack and already takes the IPv4 requests of its port, so binding the IPv4
+/// wildcard as well would fail with "address in use". Systems where IPv6 sockets only accept IPv6, like
+/// Linux with `net.ipv6.bindv6only = 1`, then answer only IPv6 on that port.
+fn drop_covered_wildcards(addrs: &mut Vec<SocketAddr>, protocol: &str) {
+    let v6_ports: Vec<u16> = addrs.iter().filter(|addr| addr.ip() == Ipv6Addr::UNSPECIFIED).map(SocketAddr::port).collect();
+    addrs.retain(|addr| {
+        let covered = addr.ip() == Ipv4Addr::UNSPECIFIED && v6_ports.contains(&addr.port());
+        if covered {
+            eprintln!("Not listening for {protocol} on {addr}: [::]:{} also accepts IPv4 requests", addr.port());
+        }
+        !covered
+    });
+}
+
+/// Parses a socket address; a bare IP address listens on the default port.
+fn parse_listen(value: &str, key: &str) -> Result<SocketAddr> {
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, DEFAULT_LISTEN.port()));
+    }
+    value.parse().with_context(|| format!("Invalid '{key}', expected an address like '127.0.0.1:53' or '[::1]:53'"))
+}
+
+/// Parses a positive number of milliseconds; none of the durations may be zero.
+fn parse_millis(value: &str, key: &str) -> Result<Duration> {
+    let millis = value.parse().with_context(|| format!("Invalid '{key}', expected milliseconds"))?;
+    if millis == 0 {
+        bail!("Invalid '{key}', expected more than 0 milliseconds");
+    }
+    Ok(Duration::from_millis(millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options> {
+        match parse_args(args.iter().map(|arg| arg.to_string()))? {
+            Command::Run(options) => Ok(options),
+            _ => bail!("expected options to run with"),
+        }
+    }
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    /// Writes `text` to a configuration file in a directory of its own, named after `test`.
+    fn config_file(test: &str, text: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("config-{test}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dns.conf");
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn parses_both_option_forms() {
+        let options = parse(&["--listen", "127.0.0.1:53", "--cache-size=5", "--listen-tcp=::1"]).unwrap();
+        assert_eq!(options.listen_udp, addrs(&["127.0.0.1:53"]));
+        assert_eq!(options.listen_tcp, addrs(&["127.0.0.1:53", "[::1]:2053"]));
+        assert_eq!(options.cache_size, 5);
+
+        assert!(matches!(parse_args(["--check-config".to_string()]), Ok(Command::CheckConfig(_))));
+        assert!(matches!(parse_args(["--workers=1".to_string(), "-h".to_string()]), Ok(Command::Help)));
+        assert!(parse(&["--workers"]).is_err());
+        assert!(parse(&["workers=1"]).is_err());
+        assert!(parse(&["--unknown=1"]).is_err());
+    }
+
+    #[test]
+    fn listens_on_the_default_address() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.listen_udp, [DEFAULT_LISTEN]);
+        assert_eq!(options.listen_tcp, [DEFAULT_LISTEN]);
+
+        // Listening on one protocol only does not add the default for the other.
+        let options = parse(&["--listen-udp", "127.0.0.1:53"]).unwrap();
+        assert!(options.listen_tcp.is_empty());
+    }
+
+    #[test]
+    fn rejects_zero_durations() {
+        for key in ["tcp-idle-timeout", "upstream-timeout", "upstream-deadline"] {
+            assert!(parse(&[&format!("--{key}=0")]).is_err(), "{key}");
+            assert!(parse(&[&format!("--{key}=1")]).is_ok(), "{key}");
+        }
+    }
+
+    #[test]
+    fn loads_configuration_files() {
+        let path = config_file(
+            "load",
+            "# Comments and empty lines are skipped.\n\
+             \n\
+             listen = 127.0.0.1:53   # trailing comment\n\
+             zone = example.com=zones/example.com.zone\n\
+             zone = example.net=/srv/example.net.zone\n\
+             upstream-timeout = 250\n",
+        );
+        let options = parse(&["--config", path.to_str().unwrap()]).unwrap();
+        assert_eq!(options.listen_udp, addrs(&["127.0.0.1:53"]));
+        assert_eq!(options.retry.attempt_timeout, Duration::from_millis(250));
+        // Repeated keys add up, relative paths are relative to the file.
+        let zones: Vec<&Path> = options.zones.iter().map(|(_, path)| path.as_path()).collect();
+        assert_eq!(zones, [path.parent().unwrap().join("zones/example.com.zone").as_path(), Path::new("/srv/example.net.zone")]);
+
+        let broken = config_file("broken", "listen = 127.0.0.1:53\nworkers\n");
+        let err = parse(&["--config", broken.to_str().unwrap()]).unwrap_err();
+        assert!(format!("{err:#}").contains("dns.conf:2"), "{err:#}");
+    }
+
+    #[test]
+    fn command_line_replaces_file_settings() {
+        let path = config_file(
+            "override",
+            "listen = 127.0.0.1:53\nlisten-udp = 127.0.0.2:53\nallow = 10.0.0.0/8\nallow = 192.168.0.0/16\nworkers = 4\ncache-size = 7\n",
+        );
+        let path = path.to_str().unwrap();
+        // The file is read first, wherever it is given.
+        let options = parse(&["--allow", "172.16.0.0/12", "--config", path, "--workers", "2", "--allow", "127.0.0.0/8"]).unwrap();
+        let allow: Vec<Network> = ["172.16.0.0/12", "127.0.0.0/8"].iter().map(|net| net.parse().unwrap()).collect();
+        assert_eq!(options.allow, allow);
+        assert_eq!(options.workers, 2);
+        assert_eq!(options.cache_size, 7);
+        assert_eq!(options.listen_udp, addrs(&["127.0.0.1:53", "127.0.0.2:53"]));
+
+        // `listen-tcp` only replaces the TCP addresses of the file.
+        let options = parse(&["--config", path, "--listen-tcp", "[::1]:53"]).unwrap();
+        assert_eq!(options.listen_udp, addrs(&["127.0.0.1:53", "127.0.0.2:53"]));
+        assert_eq!(options.listen_tcp, addrs(&["[::1]:53"]));
+    }
+
+    #[test]
+    fn combines_listen_with_listen_udp_and_listen_tcp() {
+        let options = parse(&["--listen", "127.0.0.1:53", "--listen-udp", "127.0.0.2:53"]).unwrap();
+        assert_eq!(options.listen_udp, addrs(&["127.0.0.1:53", "127.0.0.2:53"]));
+        assert_eq!(options.listen_tcp, addrs(&["127.0.0.1:53"]));
+
+        let options = parse(&["--listen-tcp", "127.0.0.2:53", "--listen", "127.0.0.1:53"]).unwrap();
+        assert_eq!(options.listen_udp, addrs(&["127.0.0.1:53"]));
+        assert_eq!(options.listen_tcp, addrs(&["127.0.0.2:53", "127.0.0.1:53"]));
+    }
+
+    #[test]
+    fn drops_ipv4_wildcards_covered_by_ipv6_wildcards() {
+        let mut listen = addrs(&["0.0.0.0:53", "[::]:53", "0.0.0.0:5353", "127.0.0.1:53", "[::1]:5353"]);
+        drop_covered_wildcards(&mut listen, "UDP");
+        assert_eq!(listen, addrs(&["[::]:53", "0.0.0.0:5353", "127.0.0.1:53", "[::1]:5353"]));
+
+        let options = parse(&["--listen", "0.0.0.0:53", "--listen", "[::]:53"]).unwrap();
+        assert_eq!(options.listen_udp, addrs(&["[::]:53"]));
+        assert_eq!(options.listen_tcp, addrs(&["[::]:53"]));
+    }
+}
@@ -1,3 +1,5 @@
 pub mod message;
 pub mod resolver;
+pub mod server;
 mod utils;
+pub mod zone;
@@ -1,52 +1,196 @@
-use anyhow::{Result, Context, bail, ensure};
-use dns_starter_rust::message::*;
-use dns_starter_rust::resolver::{DummyResolver, Resolver, ForwardingResolver};
+use anyhow::{Result, Context};
+use dns_starter_rust::message::Name;
+use dns_starter_rust::resolver::{
+    AclResolver, AuthoritativeResolver, BlocklistResolver, CachingResolver, DomainList, DummyResolver, HostsResolver,
+    LoggingResolver, Pipeline, Resolver, ForwardingResolver, RecursiveResolver, RewriteResolver, RoutingResolver,
+};
+use dns_starter_rust::server::Server;
+use dns_starter_rust::zone::Zone;
 use std::env;
-use std::net::UdpSocket;
+use std::net::{TcpListener, UdpSocket};
+use std::sync::Arc;
+use std::thread;
 
-fn create_resolver() -> Result<Box<dyn Resolver>> {
-    let mut args = env::args();
-    args.next().context("Expected first arg (path of executable)")?;
+mod config;
 
-    Ok(match args.len() {
-        0 => Box::new(DummyResolver),
-        2 => {
-            let key = args.next().unwrap();
-            let value = args.next().unwrap();
-            ensure!(key == "--resolver", "Unrecognized argument. Expected '--resolver'");
-            Box::new(ForwardingResolver::new(&value)?)
+use config::{parse_args, Command, Options, Stage, USAGE};
+
+/// Builds the resolver for `spec`, which is either `recursive` or a comma separated list of upstream addresses.
+fn build_resolver(spec: &str, options: &Options) -> Result<Box<dyn Resolver>> {
+    Ok(match spec {
+        "recursive" => {
+            let mut resolver = match &options.root_hints {
+                Some(hints) => RecursiveResolver::new(hints.clone()),
+                None => RecursiveResolver::default(),
+            };
+            resolver.retry.attempt_timeout = options.retry.attempt_timeout;
+            resolver.retry.deadline = options.retry.deadline;
+            Box::new(resolver)
         }
-        _ => {
-            bail!("Invalid number of arguments")
+        addrs => {
+            let mut resolver = ForwardingResolver::new(&addrs.split(',').collect::<Vec<_>>())?;
+            resolver.retry = options.retry;
+            resolver.strategy = options.strategy;
+            resolver.reuse_tcp = options.reuse_tcp;
+            Box::new(resolver)
         }
     })
 }
 
-fn main() -> Result<()> {
-    let udp_socket = UdpSocket::bind("127.0.0.1:2053").context("Failed to bind to address")?;
-    let mut buf = [0; 512];
-    let resolver = create_resolver()?;
-
-    loop {
-        let (size, source) = udp_socket.recv_from(&mut buf)?;
-        let msg_bytes = &buf[0..size];
-        let request = Message::from_bytes(msg_bytes);
-
-        let reply = match request.header.opcode {
-            Opcode::Query => {
-                let mut answers = Vec::new();
-                for question in &request.questions {
-                    answers.extend(resolver.resolve(question, msg_bytes)?)
+/// Appends the layer for `stage` to `pipeline`. Stages without any configuration are left out.
+fn push_stage(pipeline: &mut Pipeline, stage: Stage, options: &Options) -> Result<()> {
+    match stage {
+        Stage::Acl if !options.allow.is_empty() || !options.deny.is_empty() => {
+            let (allow, deny) = (options.allow.clone(), options.deny.clone());
+            pipeline.push(move |next| -> Result<Box<dyn Resolver>> {
+                Ok(Box::new(AclResolver::new(next, allow, deny)))
+            });
+        }
+        Stage::Log if options.log_queries => {
+            pipeline.push(|next| -> Result<Box<dyn Resolver>> { Ok(Box::new(LoggingResolver::new(next))) });
+        }
+        Stage::Hosts => {
+            if let Some(path) = options.hosts.clone() {
+                pipeline.push(move |next| -> Result<Box<dyn Resolver>> {
+                    Ok(Box::new(HostsResolver::new(path, next)?))
+                });
+            }
+        }
+        Stage::Blocklist if !options.blocklists.is_empty() => {
+            let mut blocklist = DomainList::new();
+            for path in &options.blocklists {
+                blocklist.load(path)?;
+            }
+            let mut allowlist = DomainList::new();
+            for path in &options.allowlists {
+                allowlist.load(path)?;
+            }
+            let response = options.block_response;
+            pipeline.push(move |next| -> Result<Box<dyn Resolver>> {
+                let mut resolver = BlocklistResolver::new(next, blocklist, allowlist);
+                resolver.response = response;
+                Ok(Box::new(resolver))
+            });
+        }
+        Stage::Rewrite if !options.rewrites.is_empty() => {
+            let rules = options.rewrites.clone();
+            pipeline.push(move |next| -> Result<Box<dyn Resolver>> {
+                Ok(Box::new(RewriteResolver::new(next, rules)))
+            });
+        }
+        Stage::Authoritative if !options.zones.is_empty() => {
+            let mut authoritative = AuthoritativeResolver::default();
+            for (origin, path) in &options.zones {
+                authoritative.add_zone(Zone::load(path, origin)?);
+            }
+            let authoritative = Arc::new(authoritative);
+            let origins: Vec<Name> = options.zones.iter().map(|(origin, _)| origin.clone()).collect();
+            // Names within the zones are answered from them, everything else goes to the later stages.
+            pipeline.push(move |next| -> Result<Box<dyn Resolver>> {
+                let mut router = RoutingResolver::new();
+                for origin in origins {
+                    router.add_route(origin, Box::new(Arc::clone(&authoritative)));
                 }
-                request.reply(RCode::NoError, answers)
+                router.set_default(next);
+                Ok(Box::new(router))
+            });
+        }
+        Stage::Cache if options.cache_size > 0 => {
+            let cache_size = options.cache_size;
+            pipeline.push(move |next| -> Result<Box<dyn Resolver>> {
+                Ok(Box::new(CachingResolver::new(next, cache_size)))
+            });
+        }
+        Stage::Forward => {
+            let default = options.resolver.as_deref().map(|spec| build_resolver(spec, options)).transpose()?;
+            let mut routes = Vec::new();
+            for (suffix, spec) in &options.routes {
+                routes.push((suffix.clone(), build_resolver(spec, options)?));
             }
-            Opcode::IQuery | Opcode::Status | Opcode::Reserved(_) => {
-                request.reply(RCode::NotImplemented, Vec::new())
+            if routes.is_empty() {
+                match default {
+                    Some(resolver) => {
+                        pipeline.push_resolver(resolver);
+                    }
+                    // Without anything to resolve with, a server without zones keeps answering with dummy data.
+                    None if options.zones.is_empty() => {
+                        pipeline.push_resolver(DummyResolver);
+                    }
+                    None => {}
+                }
+                return Ok(());
             }
-        };
+            // Questions matching no route go to the default resolver, or to the later stages without one.
+            pipeline.push(move |next| -> Result<Box<dyn Resolver>> {
+                let mut router = RoutingResolver::new();
+                for (suffix, resolver) in routes {
+                    router.add_route(suffix, resolver);
+                }
+                router.set_default(default.unwrap_or(next));
+                Ok(Box::new(router))
+            });
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn create_resolver(options: &Options) -> Result<Arc<dyn Resolver>> {
+    let mut pipeline = Pipeline::new();
+    for stage in &options.pipeline {
+        push_stage(&mut pipeline, *stage, options)?;
+    }
+    Ok(Arc::from(pipeline.build()?))
+}
+
+fn main() -> Result<()> {
+    let (options, check_only) = match parse_args(env::args().skip(1))? {
+        Command::Run(options) => (options, false),
+        Command::CheckConfig(options) => (options, true),
+        Command::Help => {
+            print!("{USAGE}");
+            return Ok(());
+        }
+    };
+
+    // Building the resolver loads all zones and lists, so it catches every error short of binding sockets.
+    let resolver = create_resolver(&options)?;
+    if check_only {
+        println!("Configuration OK");
+        return Ok(());
+    }
+
+    let mut udp_sockets = Vec::new();
+    for addr in &options.listen_udp {
+        udp_sockets.push(UdpSocket::bind(addr).with_context(|| format!("Failed to bind UDP socket to {addr}"))?);
+    }
+    let mut tcp_listeners = Vec::new();
+    for addr in &options.listen_tcp {
+        tcp_listeners.push(TcpListener::bind(addr).with_context(|| format!("Failed to bind TCP listener to {addr}"))?);
+    }
 
-        udp_socket
-            .send_to(&reply.as_bytes(), source)
-            .context("Failed to send response")?;
+    let mut server = Server::new(resolver);
+    server.max_udp_payload = options.max_udp_payload;
+    server.workers = options.workers;
+    server.max_tcp_connections = options.max_tcp_connections;
+    server.tcp_idle_timeout = options.tcp_idle_timeout;
+    let server = Arc::new(server);
+
+    let mut threads = Vec::new();
+    for (listener, addr) in tcp_listeners.into_iter().zip(options.listen_tcp) {
+        let server = Arc::clone(&server);
+        threads.push(thread::spawn(move || {
+            if let Err(err) = server.serve_tcp(listener) {
+                eprintln!("TCP listener on {addr} failed: {err:#}");
+            }
+        }));
+    }
+    for socket in udp_sockets {
+        let server = Arc::clone(&server);
+        threads.push(thread::spawn(move || server.serve_udp(socket)));
+    }
+    for thread in threads {
+        let _ = thread.join();
     }
+    Ok(())
 }
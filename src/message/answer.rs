@@ -1,4 +1,4 @@
-use crate::message::{QType, QClass, Name};
+use crate::message::{QType, QClass, Name, ParseError, RData, Reader, Writer};
 
 /// The question section is used to carry the "question" in most queries, i.e., the parameters that define what is being asked.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -14,15 +14,15 @@ pub struct Answer {
     /// For example, SOA records are always distributed with a zero TTL to prohibit caching.
     /// Zero values can also be used for extremely volatile data.
     pub ttl: i32,
-    /// an unsigned 16 bit integer that specifies the length in octets of the RDATA field.
-    pub rdlength: u16,
     /// a variable length string of octets that describes the resource.
     /// The format of this information varies according to the TYPE and CLASS of the resource record.
-    pub rdata: Vec<u8>,
+    ///
+    /// The RDLENGTH field is computed when the record is written.
+    pub rdata: RData,
 }
 
 impl Answer {
-    pub fn write(&self, buf: &mut Vec<u8>) {
+    pub fn write(&self, buf: &mut Writer) {
         //   0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5
         // +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
         // |                                               |
@@ -47,21 +47,32 @@ impl Answer {
         buf.extend_from_slice(&self.rtype.value().to_be_bytes());
         buf.extend_from_slice(&self.rclass.value().to_be_bytes());
         buf.extend_from_slice(&self.ttl.to_be_bytes());
-        buf.extend_from_slice(&self.rdlength.to_be_bytes());
-        buf.extend(self.rdata.iter());
+        let rdlength_pos = buf.len();
+        buf.extend_from_slice(&[0, 0]);
+        self.rdata.write(buf);
+        let rdlength = (buf.len() - rdlength_pos - 2) as u16;
+        buf[rdlength_pos..rdlength_pos + 2].copy_from_slice(&rdlength.to_be_bytes());
     }
 
-    pub fn read(buf: &mut &[u8]) -> Self {
-        let name = Name::read(buf);
+    /// an unsigned 16 bit integer that specifies the length in octets of the RDATA field.
+    ///
+    /// This is the length of the RDATA written on its own, where names are only compressed against earlier names
+    /// within it, like the RNAME of an SOA against its MNAME; written into a message the RDATA may end up shorter.
+    pub fn rdlength(&self) -> u16 {
+        let mut buf = Writer::new();
+        self.rdata.write(&mut buf);
+        buf.len() as u16
+    }
 
-        let rtype = QType::from_value(u16::from_be_bytes([buf[0], buf[1]]));
-        let rclass = QClass::from_value(u16::from_be_bytes([buf[2], buf[3]]));
-        let ttl = i32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
-        let rdlength = u16::from_be_bytes([buf[8], buf[9]]);
-        let rdata = buf[10..10 + (rdlength as usize)].to_vec();
+    pub fn read(buf: &mut Reader) -> Result<Self, ParseError> {
+        let name = Name::read(buf)?;
 
-        *buf = &buf[10 + (rdlength as usize)..];
+        let rtype = QType::from_value(buf.read_u16()?);
+        let rclass = QClass::from_value(buf.read_u16()?);
+        let ttl = buf.read_i32()?;
+        let rdlength = buf.read_u16()?;
+        let rdata = RData::read(buf, rtype, rdlength)?;
 
-        Self { name, rtype, rclass, ttl, rdlength, rdata }
+        Ok(Self { name, rtype, rclass, ttl, rdata })
     }
 }
@@ -0,0 +1,147 @@
+use crate::int_enum;
+use crate::message::{Answer, Name, ParseError, QClass, QType, RData, RCode, Reader, Writer};
+
+/// UDP payload size advertised when no other value is configured.
+///
+/// This is the value recommended by the DNS flag day 2020, which avoids IP fragmentation on virtually all networks.
+pub const DEFAULT_UDP_PAYLOAD: u16 = 1232;
+
+/// Requestors may advertise sizes smaller than 512, which must be treated as 512.
+pub const MIN_UDP_PAYLOAD: u16 = 512;
+
+int_enum! {
+    /// EDNS option codes (RFC 6891 section 6.1.2), as registered by IANA.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    OptionCode(u16) {
+        /// Long-Lived Queries (RFC 8764)
+        LLQ = 1,
+        /// Update Lease (RFC 9664)
+        UpdateLease = 2,
+        /// Name Server Identifier (RFC 5001)
+        NSID = 3,
+        /// DNSSEC Algorithm Understood (RFC 6975)
+        DAU = 5,
+        /// DS Hash Understood (RFC 6975)
+        DHU = 6,
+        /// NSEC3 Hash Understood (RFC 6975)
+        N3U = 7,
+        /// Client Subnet (RFC 7871)
+        ClientSubnet = 8,
+        /// EDNS Expire (RFC 7314)
+        Expire = 9,
+        /// DNS Cookie (RFC 7873)
+        Cookie = 10,
+        /// edns-tcp-keepalive (RFC 7828)
+        TcpKeepalive = 11,
+        /// Padding (RFC 7830)
+        Padding = 12,
+        /// Extended DNS Error (RFC 8914)
+        ExtendedError = 15,
+    }
+}
+
+/// A single option carried in the RDATA of an OPT record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdnsOption {
+    /// Assigned by the Expert Review process as defined by the DNSEXT working group and the IESG.
+    pub code: OptionCode,
+    /// Varies per OPTION-CODE.
+    pub data: Vec<u8>,
+}
+
+impl EdnsOption {
+    pub fn write(&self, buf: &mut Writer) {
+        //                 +0 (MSB)                            +1 (LSB)
+        //    +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+        // 0: |                          OPTION-CODE                          |
+        //    +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+        // 2: |                         OPTION-LENGTH                         |
+        //    +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+        // 4: |                                                               |
+        //    /                          OPTION-DATA                          /
+        //    /                                                               /
+        //    +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+        buf.extend_from_slice(&self.code.value().to_be_bytes());
+        buf.extend_from_slice(&(self.data.len() as u16).to_be_bytes());
+        buf.extend(&self.data);
+    }
+
+    pub fn read(buf: &mut Reader) -> Result<Self, ParseError> {
+        let code = OptionCode::from_value(buf.read_u16()?);
+        let len = buf.read_u16()?;
+        let data = buf.read_bytes(len as usize)?.to_vec();
+
+        Ok(Self { code, data })
+    }
+}
+
+/// The contents of the OPT pseudo-RR (RFC 6891 section 6.1).
+///
+/// The extended RCODE bits are not stored here; they are merged into [`Header::rcode`](crate::message::Header::rcode) of the message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edns {
+    /// The number of octets of the largest UDP payload that can be reassembled and delivered in the requestor's network stack.
+    pub udp_payload_size: u16,
+    /// Indicates the implementation level of the setter.
+    pub version: u8,
+    /// DNSSEC OK bit as defined by RFC 3225.
+    pub dnssec_ok: bool,
+    /// Options carried in the RDATA.
+    pub options: Vec<EdnsOption>,
+}
+
+impl Edns {
+    pub fn new(udp_payload_size: u16) -> Self {
+        Self {
+            udp_payload_size,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+
+    /// Size of the largest response the requestor is able to receive over UDP.
+    pub fn max_payload(&self) -> u16 {
+        self.udp_payload_size.max(MIN_UDP_PAYLOAD)
+    }
+
+    /// Builds the OPT pseudo-RR carrying these parameters and the upper 8 bits of `rcode`.
+    pub fn to_record(&self, rcode: RCode) -> Answer {
+        //             +0 (MSB)                            +1 (LSB)
+        //  +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+        //  |         EXTENDED-RCODE        |            VERSION            |
+        //  +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+        //  | DO|                           Z                               |
+        //  +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+        let extended_rcode = (rcode.value() >> 4) as u8;
+        let flags: u16 = if self.dnssec_ok { 0b1000_0000_0000_0000 } else { 0 };
+        let ttl = u32::from_be_bytes({
+            let [hi, lo] = flags.to_be_bytes();
+            [extended_rcode, self.version, hi, lo]
+        });
+        Answer {
+            name: Name::root(),
+            rtype: QType::OPT,
+            rclass: QClass::from_value(self.udp_payload_size),
+            ttl: ttl as i32,
+            rdata: RData::OPT(self.options.clone()),
+        }
+    }
+
+    /// Extracts the EDNS parameters and the upper 8 bits of the extended RCODE from an OPT pseudo-RR.
+    ///
+    /// Returns `None` if the record is not an OPT record.
+    pub fn from_record(record: &Answer) -> Option<(Self, u8)> {
+        let RData::OPT(options) = &record.rdata else {
+            return None;
+        };
+        let [extended_rcode, version, flags, _] = (record.ttl as u32).to_be_bytes();
+        let edns = Self {
+            udp_payload_size: record.rclass.value(),
+            version,
+            dnssec_ok: flags & 0b1000_0000 != 0,
+            options: options.clone(),
+        };
+        Some((edns, extended_rcode))
+    }
+}
@@ -0,0 +1,47 @@
+use thiserror::Error;
+
+/// Reasons why a DNS message (or a domain name) could not be decoded.
+///
+/// Every variant carries the byte offset (from the start of the message, or of the name being parsed) at which the problem was detected.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseError {
+    /// The message is shorter than the fixed 12 byte header.
+    #[error("truncated header: expected 12 bytes, got {len}")]
+    TruncatedHeader { len: usize },
+    /// A fixed size field (type, class, TTL, ...) does not fit into the remaining bytes.
+    #[error("unexpected end of message at offset {offset}: needed {needed} more bytes")]
+    UnexpectedEnd { offset: usize, needed: usize },
+    /// A label length octet points past the end of the message.
+    #[error("label at offset {offset} with length {len} overruns the message")]
+    LabelOverrun { offset: usize, len: u8 },
+    /// A label starts with one of the reserved `01` or `10` bit patterns.
+    #[error("unsupported label type at offset {offset}")]
+    BadLabelType { offset: usize },
+    /// A compression pointer does not point to an earlier position in the message.
+    #[error("bad compression pointer at offset {offset} to {target}")]
+    BadPointer { offset: usize, target: u16 },
+    /// A name is built out of more compression pointers than any valid name needs.
+    #[error("too many compression pointers in name at offset {offset}")]
+    TooManyPointers { offset: usize },
+    /// A label is longer than 63 octets.
+    #[error("label at offset {offset} is {len} octets long, at most 63 are allowed")]
+    LabelTooLong { offset: usize, len: usize },
+    /// A name is longer than 255 octets.
+    #[error("name exceeds 255 octets at offset {offset}")]
+    NameTooLong { offset: usize },
+    /// A name in presentation format contains an empty label, e.g. `example..com`.
+    #[error("empty label at offset {offset}")]
+    EmptyLabel { offset: usize },
+    /// A name in presentation format contains an invalid `\` escape.
+    #[error("invalid escape sequence at offset {offset}")]
+    BadEscape { offset: usize },
+    /// The RDLENGTH of a resource record points past the end of the message.
+    #[error("RDLENGTH {rdlength} at offset {offset} overruns the message")]
+    RDataOverrun { offset: usize, rdlength: u16 },
+    /// The RDATA does not match the format of its record type or its RDLENGTH.
+    #[error("malformed RDATA of type {rtype} at offset {offset}")]
+    BadRData { offset: usize, rtype: u16 },
+    /// The additional section contains more than one OPT pseudo-RR.
+    #[error("duplicate OPT record at offset {offset}")]
+    DuplicateOpt { offset: usize },
+}
@@ -1,4 +1,5 @@
 use crate::int_enum;
+use crate::message::{ParseError, Writer};
 
 int_enum! {
     /// A four bit field that specifies kind of query in this message.
@@ -17,8 +18,10 @@ int_enum! {
 
 int_enum! {
     /// Response code - this 4 bit field is set as part of responses.
+    ///
+    /// With EDNS(0) the response code is extended to 12 bits, the upper 8 of which are carried in the OPT record.
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    RCode(u8) {
+    RCode(u16) {
         /// No error condition
         NoError = 0,
         /// The name server was unable to interpret the query.
@@ -33,6 +36,8 @@ int_enum! {
         /// For example, a name server may not wish to provide the information to the particular requester, or a name server may not wish to perform a particular operation (e.g., zone transfer) for particular data.
         Refused = 5,
         // 6-15 Reserved for future use.
+        /// The responder does not implement the EDNS version of the request (RFC 6891).
+        BadVers = 16,
     }
 }
 
@@ -62,6 +67,8 @@ pub struct Header {
     pub recursion_available: bool,
     // Reserved (Z) 	3 bits 	Used by DNSSEC queries. At inception, it was reserved for future use.
     /// Response code - this 4 bit field is set as part of responses.
+    ///
+    /// Only the lower 4 bits are part of the header, see [`Message`](crate::message::Message) for how extended codes are handled.
     pub rcode: RCode,
     /// an unsigned 16 bit integer specifying the number of entries in the question section.
     pub question_count: u16,
@@ -74,7 +81,7 @@ pub struct Header {
 }
 
 impl Header {
-    pub fn write(&self, buf: &mut Vec<u8>) {
+    pub fn write(&self, buf: &mut Writer) {
         //                                  1  1  1  1  1  1
         //    0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5
         // +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
@@ -115,7 +122,7 @@ impl Header {
             if self.recursion_available {
                 byte |= 0b1000_0000;
             }
-            byte |= self.rcode.value();
+            byte |= (self.rcode.value() & 0b0000_1111) as u8;
             byte
         });
 
@@ -125,10 +132,13 @@ impl Header {
         buf.extend_from_slice(&self.additional_count.to_be_bytes());
     }
 
-    pub fn from_bytes(buf: &[u8]) -> Self {
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, ParseError> {
+        if buf.len() < 12 {
+            return Err(ParseError::TruncatedHeader { len: buf.len() });
+        }
         let opcode = Opcode::from_value((buf[2] & 0b0111_1000) >> 3);
-        let rcode = RCode::from_value(buf[3] & 0b0000_1111);
-        Self {
+        let rcode = RCode::from_value((buf[3] & 0b0000_1111) as u16);
+        Ok(Self {
             id: u16::from_be_bytes([buf[0], buf[1]]),
             is_reply: buf[2] & 0b1000_0000 > 0,
             opcode,
@@ -141,6 +151,6 @@ impl Header {
             answer_count: u16::from_be_bytes([buf[6], buf[7]]),
             authority_count: u16::from_be_bytes([buf[8], buf[9]]),
             additional_count: u16::from_be_bytes([buf[10], buf[11]]),
-        }
+        })
     }
 }
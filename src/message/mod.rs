@@ -1,52 +1,170 @@
 mod answer;
+mod edns;
+mod error;
 mod header;
 mod name;
 mod question;
 mod rclass;
+mod rdata;
+mod reader;
 mod rtype;
+mod writer;
 
 pub use answer::*;
+pub use edns::*;
+pub use error::*;
 pub use header::*;
 pub use question::*;
 pub use name::*;
+pub use reader::*;
 pub use rclass::*;
+pub use rdata::*;
 pub use rtype::*;
+pub use writer::*;
 
 #[derive(Debug)]
 pub struct Message {
     pub header: header::Header,
     pub questions: Vec<question::Question>,
     pub answers: Vec<answer::Answer>,
+    pub authorities: Vec<answer::Answer>,
+    pub additionals: Vec<answer::Answer>,
+    /// EDNS(0) parameters, carried as an OPT pseudo-RR at the end of the additional section.
+    ///
+    /// When present, `header.rcode` holds the full 12 bit extended response code.
+    pub edns: Option<edns::Edns>,
 }
 
 impl Message {
     pub fn as_bytes(&self) -> Vec<u8> {
-        let mut write_buf = Vec::with_capacity(512);
+        self.as_bytes_limited(usize::MAX)
+    }
+
+    /// Serializes the message into at most `max_size` bytes, e.g. the UDP payload size negotiated with the client.
+    ///
+    /// Whole RRsets are left out from the end once the limit is reached.
+    /// If answer or authority records had to be left out, the TC bit is set;
+    /// missing additional records do not set it (RFC 2181 section 9). The OPT record is always kept.
+    pub fn as_bytes_limited(&self, max_size: usize) -> Vec<u8> {
+        let mut write_buf = Writer::new();
         self.header.write(&mut write_buf);
         for q in &self.questions {
             q.write(&mut write_buf);
         }
-        for a in &self.answers {
-            a.write(&mut write_buf);
+
+        let opt = self.edns.as_ref().map(|edns| edns.to_record(self.header.rcode));
+        let reserved = opt.as_ref().map_or(0, |opt| 11 + opt.rdlength() as usize);
+        let limit = max_size.saturating_sub(reserved);
+
+        let answer_count = Self::write_rrsets(&mut write_buf, &self.answers, limit);
+        let mut truncated = answer_count < self.answers.len();
+        let authority_count = if truncated { 0 } else { Self::write_rrsets(&mut write_buf, &self.authorities, limit) };
+        truncated |= authority_count < self.authorities.len();
+        let mut additional_count = if truncated { 0 } else { Self::write_rrsets(&mut write_buf, &self.additionals, limit) };
+        if let Some(opt) = opt {
+            opt.write(&mut write_buf);
+            additional_count += 1;
+        }
+
+        let header = Header {
+            truncation: self.header.truncation || truncated,
+            question_count: self.questions.len() as u16,
+            answer_count: answer_count as u16,
+            authority_count: authority_count as u16,
+            additional_count: additional_count as u16,
+            ..self.header.clone()
+        };
+        let mut header_buf = Writer::new();
+        header.write(&mut header_buf);
+        write_buf[..header_buf.len()].copy_from_slice(&header_buf);
+
+        write_buf.into_bytes()
+    }
+
+    /// Writes as many leading RRsets of `records` as fit into `limit` bytes and returns the number of records written.
+    fn write_rrsets(buf: &mut Writer, records: &[Answer], limit: usize) -> usize {
+        let mut written = 0;
+        while written < records.len() {
+            let first = &records[written];
+            let len = records[written..]
+                .iter()
+                .take_while(|r| r.name == first.name && r.rtype == first.rtype && r.rclass == first.rclass)
+                .count();
+
+            let mark = buf.len();
+            for record in &records[written..written + len] {
+                record.write(buf);
+            }
+            if buf.len() > limit {
+                buf.rollback(mark);
+                break;
+            }
+            written += len;
         }
-        write_buf
+        written
     }
 
-    pub fn from_bytes(buf: &[u8]) -> Self {
-        let (header, mut body) = buf.split_at(12);
-        let header = Header::from_bytes(header);
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, ParseError> {
+        let mut header = Header::from_bytes(buf)?;
+        let mut body = Reader::at(buf, 12);
 
         let mut questions = Vec::with_capacity(header.question_count as usize);
         for _ in 0..header.question_count {
-            questions.push(Question::read(&mut body));
+            questions.push(Question::read(&mut body)?);
         }
 
-        let mut answers = Vec::with_capacity(header.answer_count as usize);
-        for _ in 0..header.answer_count {
-            answers.push(Answer::read(&mut body));
+        let answers = Self::read_records(&mut body, header.answer_count)?;
+        let authorities = Self::read_records(&mut body, header.authority_count)?;
+
+        let mut additionals = Vec::with_capacity(header.additional_count as usize);
+        let mut edns = None;
+        for _ in 0..header.additional_count {
+            let offset = body.offset();
+            let record = Answer::read(&mut body)?;
+            match Edns::from_record(&record) {
+                Some(_) if edns.is_some() => return Err(ParseError::DuplicateOpt { offset }),
+                Some((opt, extended_rcode)) => {
+                    header.rcode = RCode::from_value((extended_rcode as u16) << 4 | header.rcode.value());
+                    edns = Some(opt);
+                }
+                None => additionals.push(record),
+            }
         }
 
-        Self { header, questions, answers }
+        Ok(Self { header, questions, answers, authorities, additionals, edns })
+    }
+
+    fn read_records(buf: &mut Reader, count: u16) -> Result<Vec<Answer>, ParseError> {
+        let mut records = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            records.push(Answer::read(buf)?);
+        }
+        Ok(records)
+    }
+
+    /// A query for a single question, advertising EDNS(0) support with the default UDP payload size.
+    pub fn query(id: u16, question: Question, recursion_desired: bool) -> Self {
+        Self {
+            header: Header {
+                id,
+                is_reply: false,
+                opcode: Opcode::Query,
+                authoritative: false,
+                truncation: false,
+                recursion_desired,
+                recursion_available: false,
+                rcode: RCode::NoError,
+                question_count: 1,
+                answer_count: 0,
+                authority_count: 0,
+                additional_count: 0,
+            },
+            questions: vec![question],
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: Some(Edns::new(DEFAULT_UDP_PAYLOAD)),
+        }
     }
 
     pub fn reply(self, rcode: RCode, answers: Vec<Answer>) -> Self {
@@ -67,6 +185,185 @@ impl Message {
             },
             questions: self.questions,
             answers,
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    /// A response to `example.com MX`, one line per entry: the header, the question, two MX answers, two NS authorities
+    /// and the A, AAAA and A records of `mail` and `ns1` as additionals. Names are compressed against the question.
+    const RESPONSE: &[u8] = b"\
+        \x12\x34\x85\x00\x00\x01\x00\x02\x00\x02\x00\x03\
+        \x07example\x03com\x00\x00\x0f\x00\x01\
+        \xc0\x0c\x00\x0f\x00\x01\x00\x00\x01\x2c\x00\x09\x00\x0a\x04mail\xc0\x0c\
+        \xc0\x0c\x00\x0f\x00\x01\x00\x00\x01\x2c\x00\x0a\x00\x14\x05mail2\xc0\x0c\
+        \xc0\x0c\x00\x02\x00\x01\x00\x00\x0e\x10\x00\x06\x03ns1\xc0\x0c\
+        \xc0\x0c\x00\x02\x00\x01\x00\x00\x0e\x10\x00\x11\x03ns2\x07example\x03net\x00\
+        \x04mail\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\xc0\x00\x02\x01\
+        \x04mail\xc0\x0c\x00\x1c\x00\x01\x00\x00\x01\x2c\x00\x10\x20\x01\x0d\xb8\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\
+        \x03ns1\xc0\x0c\x00\x01\x00\x01\x00\x00\x0e\x10\x00\x04\xc0\x00\x02\x35";
+
+    fn record(name: &str, rtype: QType, ttl: i32, rdata: RData) -> Answer {
+        Answer { name: name.parse().unwrap(), rtype, rclass: QClass::IN, ttl, rdata }
+    }
+
+    fn response() -> Message {
+        Message::from_bytes(RESPONSE).unwrap()
+    }
+
+    fn assert_round_trip(message: &Message) -> Message {
+        let parsed = Message::from_bytes(&message.as_bytes()).unwrap();
+        assert_eq!(parsed.questions, message.questions);
+        assert_eq!(parsed.answers, message.answers);
+        assert_eq!(parsed.authorities, message.authorities);
+        assert_eq!(parsed.additionals, message.additionals);
+        assert_eq!(parsed.edns, message.edns);
+        assert_eq!(parsed.header.id, message.header.id);
+        assert_eq!(parsed.header.rcode, message.header.rcode);
+        assert!(parsed.header.is_reply && parsed.header.authoritative && !parsed.header.truncation);
+        parsed
+    }
+
+    #[test]
+    fn round_trips_all_sections() {
+        let message = Message::from_bytes(RESPONSE).unwrap();
+        assert_eq!(message.header.id, 0x1234);
+        assert!(message.header.is_reply && message.header.authoritative);
+        assert_eq!(message.questions.len(), 1);
+        assert_eq!(message.answers.len(), 2);
+        assert_eq!(message.authorities.len(), 2);
+        assert_eq!(message.additionals.len(), 3);
+        assert_eq!(message.authorities[1].rtype, QType::NS);
+        assert_eq!(message.additionals[2].rtype, QType::A);
+
+        let parsed = Message::from_bytes(&message.as_bytes()).unwrap();
+        assert_eq!(parsed.questions, message.questions);
+        assert_eq!(parsed.answers, message.answers);
+        assert_eq!(parsed.authorities, message.authorities);
+        assert_eq!(parsed.additionals, message.additionals);
+    }
+
+    #[test]
+    fn round_trips_opt() {
+        let mut message = response();
+        message.edns = Some(Edns {
+            udp_payload_size: 4096,
+            version: 0,
+            dnssec_ok: true,
+            options: vec![EdnsOption { code: OptionCode::NSID, data: b"ns1".to_vec() }],
+        });
+        let parsed = assert_round_trip(&message);
+        // The OPT record counts as an additional record, but is not among `additionals`.
+        assert_eq!(parsed.header.additional_count, 4);
+    }
+
+    #[test]
+    fn round_trips_extended_rcode() {
+        let mut message = response();
+        message.edns = Some(Edns::new(1232));
+        message.header.rcode = RCode::BadVers;
+        assert_round_trip(&message);
+
+        // Without an OPT record, only the lower 4 bits are sent.
+        message.edns = None;
+        let parsed = Message::from_bytes(&message.as_bytes()).unwrap();
+        assert_eq!(parsed.header.rcode, RCode::NoError);
+    }
+
+    #[test]
+    fn rejects_duplicate_opt() {
+        let mut message = response();
+        message.edns = Some(Edns::new(1232));
+        message.additionals.push(Edns::new(512).to_record(RCode::NoError));
+        let bytes = message.as_bytes();
+        assert!(matches!(Message::from_bytes(&bytes), Err(ParseError::DuplicateOpt { .. })));
+    }
+
+    /// An answer with a first RRset of 4 A records and a second one of 20 TXT records of 40 bytes,
+    /// which together exceed 512 bytes.
+    fn large_response() -> Message {
+        let mut message = response();
+        message.answers = (1..=4).map(|i| record("a.example.com", QType::A, 300, RData::A(Ipv4Addr::new(192, 0, 2, i)))).collect();
+        message.answers.extend((0..20).map(|i| record("b.example.com", QType::TXT, 300, RData::TXT(vec![vec![b'0' + i; 40]]))));
+        message
+    }
+
+    #[test]
+    fn truncates_at_rrset_boundaries() {
+        let message = large_response();
+        assert!(message.as_bytes().len() > 512);
+
+        let bytes = message.as_bytes_limited(512);
+        assert!(bytes.len() <= 512);
+        let parsed = Message::from_bytes(&bytes).unwrap();
+        assert!(parsed.header.truncation);
+        assert_eq!(parsed.answers, message.answers[..4]);
+        // Once the answer is incomplete, the other sections are left out as well.
+        assert!(parsed.authorities.is_empty());
+        assert!(parsed.additionals.is_empty());
+    }
+
+    #[test]
+    fn truncated_authorities_set_tc() {
+        let mut message = response();
+        message.authorities = (0..20).map(|i| record("example.com", QType::TXT, 300, RData::TXT(vec![vec![b'0' + i; 40]]))).collect();
+
+        let parsed = Message::from_bytes(&message.as_bytes_limited(512)).unwrap();
+        assert!(parsed.header.truncation);
+        assert_eq!(parsed.answers, message.answers);
+        assert!(parsed.authorities.is_empty());
+    }
+
+    #[test]
+    fn truncated_additionals_do_not_set_tc() {
+        let mut message = response();
+        message.additionals = (0..20).map(|i| record("mail.example.com", QType::TXT, 300, RData::TXT(vec![vec![b'0' + i; 40]]))).collect();
+
+        let parsed = Message::from_bytes(&message.as_bytes_limited(512)).unwrap();
+        assert!(!parsed.header.truncation);
+        assert_eq!(parsed.answers, message.answers);
+        assert_eq!(parsed.authorities, message.authorities);
+        assert!(parsed.additionals.is_empty());
+    }
+
+    #[test]
+    fn keeps_opt_when_truncating() {
+        let mut message = large_response();
+        message.edns = Some(Edns {
+            options: vec![EdnsOption { code: OptionCode::NSID, data: vec![0; 100] }],
+            ..Edns::new(1232)
+        });
+
+        let bytes = message.as_bytes_limited(512);
+        assert!(bytes.len() <= 512);
+        let parsed = Message::from_bytes(&bytes).unwrap();
+        assert!(parsed.header.truncation);
+        assert_eq!(parsed.edns, message.edns);
+        assert_eq!(parsed.answers, message.answers[..4]);
+    }
+
+    #[test]
+    fn rollback_forgets_compression_targets() {
+        let name: Name = "www.example.com".parse().unwrap();
+        let mut buf = Writer::new();
+        buf.extend_from_slice(&[0; 12]);
+        let mark = buf.len();
+        name.write(&mut buf);
+        buf.rollback(mark);
+
+        // Written again, the name must not point into the discarded bytes.
+        buf.extend_from_slice(&[0; 4]);
+        name.write(&mut buf);
+        let bytes = buf.into_bytes();
+        assert_eq!(&bytes[16..], b"\x03www\x07example\x03com\x00");
+        assert_eq!(Name::read(&mut Reader::at(&bytes, 16)).unwrap(), name);
+    }
+}
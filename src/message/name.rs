@@ -1,55 +1,339 @@
-#[derive(Debug, Clone, PartialEq, Eq)]
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use crate::message::{ParseError, Reader, Writer};
+
+/// Maximum length of a single label in octets.
+pub const MAX_LABEL_LEN: usize = 63;
+/// Maximum length of a name in its uncompressed wire format, including the length octets and the terminating root label.
+pub const MAX_NAME_LEN: usize = 255;
+/// Maximum number of compression pointers followed while reading a single name.
+const MAX_POINTER_HOPS: usize = 128;
+
+/// A fully decompressed domain name.
+///
+/// Names are compared and hashed case-insensitively, as required by RFC 1035 section 2.3.3.
+#[derive(Debug, Clone)]
 pub struct Name {
     parts: Vec<Vec<u8>>,
-    pointer: Option<u16>,
 }
 
 impl Name {
-    pub fn write(&self, buf: &mut Vec<u8>) {
-        for part in &self.parts {
+    /// The root name (`.`).
+    pub fn root() -> Self {
+        Self { parts: Vec::new() }
+    }
+
+    /// Creates a name from its labels, ordered from the leftmost (most specific) one.
+    ///
+    /// Error offsets refer to the uncompressed wire format of the name.
+    pub fn from_labels<I>(labels: I) -> Result<Self, ParseError>
+    where
+        I: IntoIterator,
+        I::Item: Into<Vec<u8>>,
+    {
+        let mut wire_len = 1;
+        let mut parts = Vec::new();
+        for label in labels {
+            let label = label.into();
+            let offset = wire_len - 1;
+            if label.is_empty() {
+                return Err(ParseError::EmptyLabel { offset });
+            }
+            if label.len() > MAX_LABEL_LEN {
+                return Err(ParseError::LabelTooLong { offset, len: label.len() });
+            }
+            wire_len += label.len() + 1;
+            if wire_len > MAX_NAME_LEN {
+                return Err(ParseError::NameTooLong { offset });
+            }
+            parts.push(label);
+        }
+        Ok(Self { parts })
+    }
+
+    /// The labels of this name, from the leftmost (most specific) one. The root label is not included.
+    pub fn labels(&self) -> &[Vec<u8>] {
+        &self.parts
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.parts.is_empty()
+    }
+
+    /// Whether this name is equal to `other` or lies below it, e.g. `www.example.com` is a subdomain of `example.com` and of `.`.
+    pub fn is_subdomain_of(&self, other: &Name) -> bool {
+        self.parts.len() >= other.parts.len()
+            && self.parts[self.parts.len() - other.parts.len()..]
+                .iter()
+                .zip(&other.parts)
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    /// Writes the name, replacing the longest suffix already present in `buf` with a compression pointer.
+    pub fn write(&self, buf: &mut Writer) {
+        for (i, part) in self.parts.iter().enumerate() {
+            let suffix = &self.parts[i..];
+            if let Some(offset) = buf.find_suffix(suffix) {
+                let bytes = offset.to_be_bytes();
+                buf.push(0b1100_0000 | bytes[0]);
+                buf.push(bytes[1]);
+                return;
+            }
+            buf.add_suffix(suffix);
             buf.push(part.len() as u8);
             buf.extend(part);
         }
-        if let Some(pointer) = self.pointer {
-            let bytes = pointer.to_be_bytes();
-            buf.push(0b1100_0000 | bytes[0]);
-            buf.push(bytes[1]);
-        } else {
-            buf.push(0);
-        }
+        buf.push(0);
     }
 
-    pub fn read(buf: &mut &[u8]) -> Self {
-        let mut name = Self {
-            parts: Vec::new(),
-            pointer: None,
-        };
+    /// Reads a name, following compression pointers.
+    ///
+    /// Only pointers to earlier positions in the message are accepted, which rules out loops.
+    pub fn read(buf: &mut Reader) -> Result<Self, ParseError> {
+        let msg = buf.message();
+        let mut cursor = *buf;
+        let mut jumped = false;
+        let mut hops = 0;
+        let mut wire_len = 1;
+        let mut parts = Vec::new();
         loop {
-            if buf[0] == 0 {
-                *buf = &buf[1..];
-                break;
-            } else if buf[0] & 0b1100_0000 == 0b1100_0000 {
-                let index = u16::from_be_bytes([buf[0] & 0b0011_1111, buf[1]]);
-                *buf = &buf[2..];
-                name.pointer = Some(index);
+            let offset = cursor.offset();
+            let len = cursor.read_u8()?;
+            if len == 0 {
                 break;
+            } else if len & 0b1100_0000 == 0b1100_0000 {
+                let low = cursor.read_u8()?;
+                let target = u16::from_be_bytes([len & 0b0011_1111, low]);
+                if target as usize >= offset {
+                    return Err(ParseError::BadPointer { offset, target });
+                }
+                hops += 1;
+                if hops > MAX_POINTER_HOPS {
+                    return Err(ParseError::TooManyPointers { offset });
+                }
+                if !jumped {
+                    *buf = cursor;
+                    jumped = true;
+                }
+                cursor = Reader::at(msg, target as usize);
+            } else if len & 0b1100_0000 != 0 {
+                return Err(ParseError::BadLabelType { offset });
             } else {
-                let len = buf[0] as usize;
-                name.parts.push(buf[1..1 + len].to_vec());
-                *buf = &buf[1 + len..];
+                if cursor.remaining() < len as usize {
+                    return Err(ParseError::LabelOverrun { offset, len });
+                }
+                wire_len += len as usize + 1;
+                if wire_len > MAX_NAME_LEN {
+                    return Err(ParseError::NameTooLong { offset });
+                }
+                parts.push(cursor.read_bytes(len as usize)?.to_vec());
             }
         }
+        if !jumped {
+            *buf = cursor;
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.parts.len() == other.parts.len()
+            && self.parts.iter().zip(&other.parts).all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+}
+
+impl Eq for Name {}
 
-        name
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.parts.len());
+        for part in &self.parts {
+            state.write_usize(part.len());
+            for b in part {
+                state.write_u8(b.to_ascii_lowercase());
+            }
+        }
     }
+}
 
-    pub(crate) fn resolve(&self, msg: &[u8]) -> Self {
-        let mut name = self.clone();
-        while let Some(pointer) = name.pointer {
-            let next = Self::read(&mut &msg[pointer as usize..]);
-            name.parts.extend(next.parts);
-            name.pointer = next.pointer;
+/// Formats the name in presentation format, e.g. `www.example.com`.
+/// The root name is formatted as `.`; special characters are escaped as in master files.
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.parts.is_empty() {
+            return f.write_str(".");
+        }
+        for (i, part) in self.parts.iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            for &b in part {
+                match b {
+                    b'.' | b'\\' => write!(f, "\\{}", b as char)?,
+                    0x21..=0x7E => write!(f, "{}", b as char)?,
+                    _ => write!(f, "\\{:03}", b)?,
+                }
+            }
         }
-        name
+        Ok(())
+    }
+}
+
+/// Parses a name in presentation format. A trailing dot is optional; `\X` and `\DDD` escapes are supported.
+impl FromStr for Name {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "." {
+            return Ok(Self::root());
+        }
+        let bytes = s.as_bytes();
+        let mut labels = Vec::new();
+        let mut label = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'.' => {
+                    if label.is_empty() {
+                        return Err(ParseError::EmptyLabel { offset: i });
+                    }
+                    labels.push(std::mem::take(&mut label));
+                }
+                b'\\' => {
+                    let digits = bytes.get(i + 1..i + 4).filter(|d| d.iter().all(u8::is_ascii_digit));
+                    if let Some(digits) = digits {
+                        let value = digits.iter().fold(0u16, |n, d| n * 10 + (d - b'0') as u16);
+                        label.push(u8::try_from(value).map_err(|_| ParseError::BadEscape { offset: i })?);
+                        i += 3;
+                    } else {
+                        label.push(*bytes.get(i + 1).ok_or(ParseError::BadEscape { offset: i })?);
+                        i += 1;
+                    }
+                }
+                b => label.push(b),
+            }
+            i += 1;
+        }
+        if !label.is_empty() {
+            labels.push(label);
+        }
+        Self::from_labels(labels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(msg: &[u8], pos: usize) -> Result<Name, ParseError> {
+        Name::read(&mut Reader::at(msg, pos))
+    }
+
+    #[test]
+    fn reads_compressed_names() {
+        // `example.com` at offset 0, then `www` followed by a pointer to it.
+        let msg = b"\x07example\x03com\x00\x03www\xc0\x00";
+        let mut reader = Reader::at(msg, 13);
+        let name = Name::read(&mut reader).unwrap();
+        assert_eq!(name, "www.example.com".parse().unwrap());
+        assert_eq!(reader.offset(), msg.len());
+    }
+
+    #[test]
+    fn writes_compressed_names() {
+        let msg = b"\x07example\x03com\x00\x03www\x07EXAMPLE\x03com\x00";
+        let mut reader = Reader::new(msg);
+        let mut buf = Writer::new();
+        Name::read(&mut reader).unwrap().write(&mut buf);
+        Name::read(&mut reader).unwrap().write(&mut buf);
+        assert_eq!(&buf[..], b"\x07example\x03com\x00\x03www\xc0\x00");
+    }
+
+    #[test]
+    fn rejects_forward_and_self_pointers() {
+        assert_eq!(read(b"\xc0\x02\x00", 0), Err(ParseError::BadPointer { offset: 0, target: 2 }));
+        assert_eq!(read(b"\x00\xc0\x01", 1), Err(ParseError::BadPointer { offset: 1, target: 1 }));
+        // A pointer to a pointer to itself.
+        assert_eq!(read(b"\x00\xc0\x01\xc0\x01", 3), Err(ParseError::BadPointer { offset: 1, target: 1 }));
+    }
+
+    #[test]
+    fn limits_pointer_hops() {
+        // The root name at offset 0, followed by pointers to the previous pointer.
+        let chain = |pointers: usize| {
+            let mut msg = vec![0];
+            for i in 0..pointers {
+                let target = if i == 0 { 0 } else { 1 + 2 * (i - 1) as u16 };
+                msg.extend_from_slice(&(0xc000 | target).to_be_bytes());
+            }
+            msg
+        };
+
+        let msg = chain(MAX_POINTER_HOPS);
+        assert_eq!(read(&msg, msg.len() - 2), Ok(Name::root()));
+        let msg = chain(MAX_POINTER_HOPS + 1);
+        assert_eq!(read(&msg, msg.len() - 2), Err(ParseError::TooManyPointers { offset: 1 }));
+    }
+
+    #[test]
+    fn limits_name_length() {
+        let label = |len: usize| [vec![len as u8], vec![b'a'; len]].concat();
+        let longest = [label(63), label(63), label(63), label(61), vec![0]].concat();
+        assert_eq!(read(&longest, 0).unwrap().labels().len(), 4);
+
+        let too_long = [label(63), label(63), label(63), label(62), vec![0]].concat();
+        assert_eq!(read(&too_long, 0), Err(ParseError::NameTooLong { offset: 192 }));
+
+        // The limit applies to the decompressed name, the third label read from the pointer target exceeds it.
+        let compressed = [label(63), label(63), label(63), vec![0], label(62), vec![0xc0, 0x00]].concat();
+        assert_eq!(read(&compressed, 193), Err(ParseError::NameTooLong { offset: 128 }));
+
+        let labels = vec![vec![b'a'; 63], vec![b'a'; 63], vec![b'a'; 63], vec![b'a'; 62]];
+        assert_eq!(Name::from_labels(labels), Err(ParseError::NameTooLong { offset: 192 }));
+    }
+
+    #[test]
+    fn rejects_bad_labels() {
+        assert_eq!(read(b"\x05abc", 0), Err(ParseError::LabelOverrun { offset: 0, len: 5 }));
+        assert_eq!(read(b"\x40abc", 0), Err(ParseError::BadLabelType { offset: 0 }));
+        assert_eq!(
+            Name::from_str(&"a".repeat(64)),
+            Err(ParseError::LabelTooLong { offset: 0, len: 64 })
+        );
+        assert_eq!(Name::from_str("example..com"), Err(ParseError::EmptyLabel { offset: 8 }));
+    }
+
+    #[test]
+    fn parses_presentation_format() {
+        assert_eq!(Name::from_str(".").unwrap(), Name::root());
+        assert_eq!(Name::from_str("example.com.").unwrap(), Name::from_str("example.com").unwrap());
+        assert_eq!(
+            Name::from_str("a\\.b.\\065\\\\c").unwrap().labels(),
+            [b"a.b".to_vec(), b"A\\c".to_vec()]
+        );
+        assert_eq!(Name::from_str("\\000\\255").unwrap().labels(), [vec![0, 255]]);
+        assert_eq!(Name::from_str("\\256"), Err(ParseError::BadEscape { offset: 0 }));
+        assert_eq!(Name::from_str("abc\\"), Err(ParseError::BadEscape { offset: 3 }));
+    }
+
+    #[test]
+    fn formats_with_escapes() {
+        assert_eq!(Name::root().to_string(), ".");
+        let name = Name::from_labels([b"a.b\\c".to_vec(), b" \x01\x7f".to_vec(), b"com".to_vec()]).unwrap();
+        assert_eq!(name.to_string(), "a\\.b\\\\c.\\032\\001\\127.com");
+        assert_eq!(name.to_string().parse::<Name>().unwrap().labels(), name.labels());
+    }
+
+    #[test]
+    fn compares_case_insensitively() {
+        let lower: Name = "www.example.com".parse().unwrap();
+        let mixed: Name = "WWW.Example.COM".parse().unwrap();
+        assert_eq!(lower, mixed);
+        assert!(mixed.is_subdomain_of(&"example.com".parse().unwrap()));
+        assert!(!lower.is_subdomain_of(&"ample.com".parse().unwrap()));
     }
 }
@@ -1,7 +1,7 @@
-use crate::message::{QType, QClass, Name};
+use crate::message::{QType, QClass, Name, ParseError, Reader, Writer};
 
 /// The question section is used to carry the "question" in most queries, i.e., the parameters that define what is being asked.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Question {
     /// a domain name represented as a sequence of labels, where each label consists of a length octet followed by that number of octets.
     /// The domain name terminates with the zero length octet for the null label of the root.
@@ -16,7 +16,7 @@ pub struct Question {
 }
 
 impl Question {
-    pub fn write(&self, buf: &mut Vec<u8>) {
+    pub fn write(&self, buf: &mut Writer) {
         //                                 1  1  1  1  1  1
         //   0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5
         // +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
@@ -34,16 +34,11 @@ impl Question {
         buf.extend_from_slice(&self.qclass.value().to_be_bytes());
     }
 
-    pub fn read(buf: &mut &[u8]) -> Self {
-        let qname = Name::read(buf);
-        let qtype = QType::from_value(u16::from_be_bytes([buf[0], buf[1]]));
-        let qclass = QClass::from_value(u16::from_be_bytes([buf[2], buf[3]]));
-        *buf = &buf[4..];
+    pub fn read(buf: &mut Reader) -> Result<Self, ParseError> {
+        let qname = Name::read(buf)?;
+        let qtype = QType::from_value(buf.read_u16()?);
+        let qclass = QClass::from_value(buf.read_u16()?);
 
-        Self { qname, qtype, qclass }
-    }
-
-    pub fn with_resolved_name(&self, msg: &[u8]) -> Self {
-        Self { qname: self.qname.resolve(msg), qtype: self.qtype, qclass: self.qclass }
+        Ok(Self { qname, qtype, qclass })
     }
 }
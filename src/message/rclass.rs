@@ -6,7 +6,7 @@ int_enum! {
     ///
     /// QCLASS fields appear in the question section of a query.
     /// QCLASS values are a superset of CLASS values; every CLASS is a valid QCLASS.
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     QClass(u16) {
         /// the Internet
         IN = 1,
@@ -0,0 +1,211 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::message::{EdnsOption, Name, ParseError, QType, Reader, Writer};
+
+/// The decoded RDATA of a resource record.
+///
+/// Domain names embedded in the RDATA are fully decompressed against the message they were read from,
+/// so the values stay valid when moved into a different message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RData {
+    /// a 32 bit Internet address.
+    A(Ipv4Addr),
+    /// a host which should be authoritative for the specified class and domain.
+    NS(Name),
+    /// the canonical or primary name for the owner. The owner name is an alias.
+    CNAME(Name),
+    /// marks the start of a zone of authority
+    SOA {
+        /// the domain-name of the name server that was the original or primary source of data for this zone.
+        mname: Name,
+        /// a domain-name which specifies the mailbox of the person responsible for this zone.
+        rname: Name,
+        /// the unsigned 32 bit version number of the original copy of the zone.
+        serial: u32,
+        /// a 32 bit time interval before the zone should be refreshed.
+        refresh: u32,
+        /// a 32 bit time interval that should elapse before a failed refresh should be retried.
+        retry: u32,
+        /// a 32 bit time value that specifies the upper limit on the time interval that can elapse before the zone is no longer authoritative.
+        expire: u32,
+        /// the unsigned 32 bit minimum TTL field that should be exported with any RR from this zone.
+        minimum: u32,
+    },
+    /// a host which has a mail agent for the domain which should be able to deliver mail for the domain.
+    /// Obsolete, replaced by MX.
+    MD(Name),
+    /// a host which has a mail agent for the domain which will accept mail for forwarding to the domain.
+    /// Obsolete, replaced by MX.
+    MF(Name),
+    /// a host which has the specified mailbox.
+    MB(Name),
+    /// a mailbox which is a member of the mail group specified by the owner name.
+    MG(Name),
+    /// a mailbox which is the proper rename of the mailbox specified by the owner name.
+    MR(Name),
+    /// mailbox or mail list information
+    MINFO {
+        /// a mailbox which is responsible for the mailing list or mailbox.
+        rmailbx: Name,
+        /// a mailbox which is to receive error messages related to the mailing list or mailbox.
+        emailbx: Name,
+    },
+    /// a domain-name which points to some location in the domain name space.
+    PTR(Name),
+    /// mail exchange
+    MX {
+        /// a 16 bit integer which specifies the preference given to this RR among others at the same owner.
+        /// Lower values are preferred.
+        preference: u16,
+        /// a domain-name which specifies a host willing to act as a mail exchange for the owner name.
+        exchange: Name,
+    },
+    /// one or more character-strings.
+    TXT(Vec<Vec<u8>>),
+    /// a 128 bit IPv6 address.
+    AAAA(Ipv6Addr),
+    /// the options of the EDNS(0) OPT pseudo-RR, see [`Edns`](crate::message::Edns).
+    OPT(Vec<EdnsOption>),
+    /// RDATA of a type this crate does not decode, kept verbatim.
+    ///
+    /// Only the RFC 1035 types, which are all decoded above, may compress the names in their RDATA
+    /// (RFC 3597 section 4), so verbatim RDATA does not depend on the message it was read from.
+    Unknown(Vec<u8>),
+}
+
+impl RData {
+    /// Writes the RDATA, compressing the embedded domain names of the RFC 1035 types.
+    pub fn write(&self, buf: &mut Writer) {
+        match self {
+            Self::A(addr) => buf.extend_from_slice(&addr.octets()),
+            Self::NS(name)
+            | Self::CNAME(name)
+            | Self::MD(name)
+            | Self::MF(name)
+            | Self::MB(name)
+            | Self::MG(name)
+            | Self::MR(name)
+            | Self::PTR(name) => name.write(buf),
+            Self::SOA { mname, rname, serial, refresh, retry, expire, minimum } => {
+                mname.write(buf);
+                rname.write(buf);
+                for n in [serial, refresh, retry, expire, minimum] {
+                    buf.extend_from_slice(&n.to_be_bytes());
+                }
+            }
+            Self::MINFO { rmailbx, emailbx } => {
+                rmailbx.write(buf);
+                emailbx.write(buf);
+            }
+            Self::MX { preference, exchange } => {
+                buf.extend_from_slice(&preference.to_be_bytes());
+                exchange.write(buf);
+            }
+            Self::TXT(strings) => {
+                for s in strings {
+                    buf.push(s.len() as u8);
+                    buf.extend(s);
+                }
+            }
+            Self::AAAA(addr) => buf.extend_from_slice(&addr.octets()),
+            Self::OPT(options) => {
+                for option in options {
+                    option.write(buf);
+                }
+            }
+            Self::Unknown(data) => buf.extend(data),
+        }
+    }
+
+    /// Reads `rdlength` bytes of RDATA for a record of the given type.
+    pub fn read(buf: &mut Reader, rtype: QType, rdlength: u16) -> Result<Self, ParseError> {
+        let start = buf.offset();
+        if buf.remaining() < rdlength as usize {
+            return Err(ParseError::RDataOverrun { offset: start, rdlength });
+        }
+        let end = start + rdlength as usize;
+
+        let rdata = match rtype {
+            QType::A => {
+                let b = buf.read_bytes(4)?;
+                Self::A(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+            }
+            QType::AAAA => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(buf.read_bytes(16)?);
+                Self::AAAA(Ipv6Addr::from(octets))
+            }
+            QType::NS => Self::NS(Name::read(buf)?),
+            QType::CNAME => Self::CNAME(Name::read(buf)?),
+            QType::MD => Self::MD(Name::read(buf)?),
+            QType::MF => Self::MF(Name::read(buf)?),
+            QType::MB => Self::MB(Name::read(buf)?),
+            QType::MG => Self::MG(Name::read(buf)?),
+            QType::MR => Self::MR(Name::read(buf)?),
+            QType::MINFO => Self::MINFO {
+                rmailbx: Name::read(buf)?,
+                emailbx: Name::read(buf)?,
+            },
+            QType::PTR => Self::PTR(Name::read(buf)?),
+            QType::SOA => Self::SOA {
+                mname: Name::read(buf)?,
+                rname: Name::read(buf)?,
+                serial: buf.read_u32()?,
+                refresh: buf.read_u32()?,
+                retry: buf.read_u32()?,
+                expire: buf.read_u32()?,
+                minimum: buf.read_u32()?,
+            },
+            QType::MX => Self::MX {
+                preference: buf.read_u16()?,
+                exchange: Name::read(buf)?,
+            },
+            QType::TXT => {
+                let mut strings = Vec::new();
+                while buf.offset() < end {
+                    let len = buf.read_u8()?;
+                    strings.push(buf.read_bytes(len as usize)?.to_vec());
+                }
+                Self::TXT(strings)
+            }
+            QType::OPT => {
+                let mut options = Vec::new();
+                while buf.offset() < end {
+                    options.push(EdnsOption::read(buf)?);
+                }
+                Self::OPT(options)
+            }
+            _ => Self::Unknown(buf.read_bytes(rdlength as usize)?.to_vec()),
+        };
+
+        if buf.offset() != end {
+            return Err(ParseError::BadRData { offset: start, rtype: rtype.value() });
+        }
+        Ok(rdata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(rdata: &RData) -> Vec<u8> {
+        let mut buf = Writer::new();
+        rdata.write(&mut buf);
+        buf.into_bytes()
+    }
+
+    #[test]
+    fn decompresses_names_of_obsolete_mail_types() {
+        // `example.com` at offset 0, followed by the RDATA of MB, MINFO and MR records pointing into it.
+        let msg = b"\x07example\x03com\x00\x04mail\xc0\x00\x05admin\xc0\x00\x06errors\xc0\x00\xc0\x08";
+        let mut reader = Reader::at(msg, 13);
+        let mb = RData::read(&mut reader, QType::MB, 7).unwrap();
+        assert_eq!(bytes(&mb), b"\x04mail\x07example\x03com\x00");
+        // Written into another message, the names are complete, only compressed against each other.
+        let minfo = RData::read(&mut reader, QType::MINFO, 17).unwrap();
+        assert_eq!(bytes(&minfo), b"\x05admin\x07example\x03com\x00\x06errors\xc0\x06");
+        let mr = RData::read(&mut reader, QType::MR, 2).unwrap();
+        assert_eq!(bytes(&mr), b"\x03com\x00");
+    }
+}
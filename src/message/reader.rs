@@ -0,0 +1,78 @@
+use crate::message::ParseError;
+
+/// A cursor over a complete DNS message.
+///
+/// Keeps the whole message around, so that compression pointers can be followed
+/// and errors can report the offset at which they occurred.
+#[derive(Debug, Clone, Copy)]
+pub struct Reader<'a> {
+    msg: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(msg: &'a [u8]) -> Self {
+        Self { msg, pos: 0 }
+    }
+
+    /// Creates a reader positioned at `pos` within `msg`.
+    pub fn at(msg: &'a [u8], pos: usize) -> Self {
+        Self { msg, pos }
+    }
+
+    /// Offset of the next unread byte from the start of the message.
+    pub fn offset(&self) -> usize {
+        self.pos
+    }
+
+    /// The complete message this reader operates on.
+    pub fn message(&self) -> &'a [u8] {
+        self.msg
+    }
+
+    /// Number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.msg.len().saturating_sub(self.pos)
+    }
+
+    pub fn peek_u8(&self) -> Result<u8, ParseError> {
+        self.ensure(1)?;
+        Ok(self.msg[self.pos])
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, ParseError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, ParseError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, ParseError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, ParseError> {
+        self.read_u32().map(|n| n as i32)
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        self.ensure(len)?;
+        let bytes = &self.msg[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn ensure(&self, len: usize) -> Result<(), ParseError> {
+        if self.remaining() < len {
+            Err(ParseError::UnexpectedEnd {
+                offset: self.pos,
+                needed: len - self.remaining(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -7,7 +7,7 @@ int_enum! {
     ///
     /// QTYPE fields appear in the question part of a query.
     /// QTYPES are a superset of TYPEs, hence all TYPEs are valid QTYPEs.
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     QType(u16) {
         /// a host address
         A = 1,
@@ -41,6 +41,10 @@ int_enum! {
         MX = 15,
         /// text strings
         TXT = 16,
+        /// a host IPv6 address (RFC 3596)
+        AAAA = 28,
+        /// the EDNS(0) pseudo-RR (RFC 6891)
+        OPT = 41,
 
         // QTYPE specific
         /// A request for a transfer of an entire zone
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+/// Largest offset a compression pointer can refer to (14 bits).
+const MAX_POINTER_OFFSET: usize = 0x3FFF;
+
+/// A buffer for serializing a DNS message.
+///
+/// Besides the bytes written so far, it remembers where each domain name suffix was written,
+/// so that repeated suffixes can be replaced by compression pointers (RFC 1035 section 4.1.4).
+#[derive(Debug, Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+    names: HashMap<Vec<Vec<u8>>, u16>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self {
+            buf: Vec::with_capacity(512),
+            names: HashMap::new(),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    /// Returns the offset of a previously written name with the given labels, if any.
+    ///
+    /// Labels are compared case-insensitively.
+    pub(crate) fn find_suffix(&self, labels: &[Vec<u8>]) -> Option<u16> {
+        self.names.get(&Self::key(labels)).copied()
+    }
+
+    /// Records that a name with the given labels starts at the current offset.
+    pub(crate) fn add_suffix(&mut self, labels: &[Vec<u8>]) {
+        let offset = self.buf.len();
+        if offset <= MAX_POINTER_OFFSET {
+            self.names.entry(Self::key(labels)).or_insert(offset as u16);
+        }
+    }
+
+    /// Discards everything written from offset `len` onwards, including the compression targets within it.
+    pub(crate) fn rollback(&mut self, len: usize) {
+        self.buf.truncate(len);
+        self.names.retain(|_, offset| (*offset as usize) < len);
+    }
+
+    fn key(labels: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        labels.iter().map(|label| label.to_ascii_lowercase()).collect()
+    }
+}
+
+impl Deref for Writer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+impl DerefMut for Writer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
@@ -0,0 +1,174 @@
+use anyhow::{bail, Context, Result};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::message::{Question, RCode};
+
+use super::{Request, Resolver, Response};
+
+/// A range of IP addresses given by a prefix, e.g. `192.168.0.0/16` or `fd00::/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Network {
+    /// The network of the addresses starting with the first `prefix_len` bits of `addr`.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self> {
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix_len > max_len {
+            bail!("Prefix length {prefix_len} is longer than the address");
+        }
+        Ok(Self { addr, prefix_len })
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        // Clients of dual-stack sockets show up with IPv4-mapped IPv6 addresses.
+        match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parses `<address>/<prefix length>`, or a single address.
+impl FromStr for Network {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len.parse().with_context(|| format!("Invalid prefix length in '{s}'"))?)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().with_context(|| format!("Invalid address in '{s}'"))?;
+        let prefix_len = prefix_len.unwrap_or(if addr.is_ipv4() { 32 } else { 128 });
+        Self::new(addr, prefix_len)
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// Refuses questions of clients which are not allowed to use the inner resolver.
+///
+/// A client is allowed if its address is in none of the denied networks and, unless there are none,
+/// in one of the allowed networks.
+pub struct AclResolver<R> {
+    inner: R,
+    allow: Vec<Network>,
+    deny: Vec<Network>,
+}
+
+impl<R: Resolver> AclResolver<R> {
+    pub fn new(inner: R, allow: Vec<Network>, deny: Vec<Network>) -> Self {
+        Self { inner, allow, deny }
+    }
+
+    pub fn is_allowed(&self, client: IpAddr) -> bool {
+        !self.deny.iter().any(|network| network.contains(client))
+            && (self.allow.is_empty() || self.allow.iter().any(|network| network.contains(client)))
+    }
+}
+
+impl<R: Resolver> Resolver for AclResolver<R> {
+    fn resolve(&self, question: &Question, request: &Request) -> Result<Response> {
+        if !self.is_allowed(request.client.ip()) {
+            return Ok(Response::new(RCode::Refused));
+        }
+        self.inner.resolve(question, request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use super::*;
+    use crate::message::{Message, QClass, QType};
+    use crate::resolver::{DummyResolver, Transport};
+
+    fn network(text: &str) -> Network {
+        text.parse().unwrap()
+    }
+
+    fn addr(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_networks() {
+        assert_eq!(network("10.0.0.1").to_string(), "10.0.0.1/32");
+        assert_eq!(network("fd00::/8").to_string(), "fd00::/8");
+        for text in ["10.0.0.0/33", "fd00::/129", "10.0.0.0/", "10.0.0/8", "example.com"] {
+            assert!(text.parse::<Network>().is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn contains_addresses_with_the_prefix() {
+        let network = self::network("192.168.0.0/16");
+        assert!(network.contains(addr("192.168.0.1")));
+        assert!(network.contains(addr("192.168.255.255")));
+        assert!(!network.contains(addr("192.169.0.1")));
+        assert!(!network.contains(addr("fd00::1")));
+
+        let network = self::network("fd00::/8");
+        assert!(network.contains(addr("fd12:3456::1")));
+        assert!(!network.contains(addr("fe80::1")));
+        assert!(!network.contains(addr("10.0.0.1")));
+
+        assert!(self::network("10.0.0.1").contains(addr("10.0.0.1")));
+        assert!(!self::network("10.0.0.1").contains(addr("10.0.0.2")));
+    }
+
+    #[test]
+    fn contains_ipv4_mapped_addresses() {
+        let network = self::network("192.168.0.0/16");
+        assert!(network.contains(addr("::ffff:192.168.1.1")));
+        assert!(!network.contains(addr("::ffff:10.0.0.1")));
+    }
+
+    #[test]
+    fn zero_prefix_contains_the_whole_family() {
+        assert!(network("0.0.0.0/0").contains(addr("203.0.113.1")));
+        assert!(network("0.0.0.0/0").contains(addr("::ffff:203.0.113.1")));
+        assert!(!network("0.0.0.0/0").contains(addr("2001:db8::1")));
+        assert!(network("::/0").contains(addr("2001:db8::1")));
+        assert!(!network("::/0").contains(addr("203.0.113.1")));
+    }
+
+    #[test]
+    fn refuses_denied_and_unlisted_clients() {
+        let resolver = AclResolver::new(DummyResolver, vec![network("10.0.0.0/8")], vec![network("10.0.0.13")]);
+        let rcode = |client: &str| {
+            let question = Question { qname: "www.example.com".parse().unwrap(), qtype: QType::A, qclass: QClass::IN };
+            let request = Request {
+                client: SocketAddr::new(addr(client), 53000),
+                transport: Transport::Udp,
+                message: Message::query(0, question.clone(), true),
+            };
+            resolver.resolve(&question, &request).unwrap().rcode
+        };
+        assert_eq!(rcode("10.1.2.3"), RCode::NoError);
+        assert_eq!(rcode("10.0.0.13"), RCode::Refused);
+        assert_eq!(rcode("192.0.2.1"), RCode::Refused);
+
+        // Without allowed networks, everyone but the denied is allowed.
+        let resolver = AclResolver::new(DummyResolver, Vec::new(), vec![network("10.0.0.13")]);
+        assert!(resolver.is_allowed(addr("192.0.2.1")));
+        assert!(!resolver.is_allowed(addr("::ffff:10.0.0.13")));
+        assert!(resolver.is_allowed(Ipv4Addr::LOCALHOST.into()));
+    }
+}
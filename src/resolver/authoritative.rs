@@ -0,0 +1,153 @@
+use anyhow::Result;
+
+use crate::message::{Answer, Name, QClass, QType, Question, RCode, RData};
+use crate::zone::Zone;
+
+use super::{Request, Resolver, Response};
+
+/// Maximum number of CNAMEs followed within a zone.
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// Answers questions from the zones it is authoritative for, following RFC 1034 section 4.3.2.
+///
+/// Questions are answered from the zone with the longest origin containing the name in question;
+/// questions outside of all zones, or of a class other than IN, are refused. Names below a zone cut
+/// are answered with a referral to the delegated name servers, including glue from the zone.
+#[derive(Debug, Default)]
+pub struct AuthoritativeResolver {
+    zones: Vec<Zone>,
+}
+
+impl AuthoritativeResolver {
+    pub fn new(zones: Vec<Zone>) -> Self {
+        Self { zones }
+    }
+
+    /// Adds `zone`, replacing any zone with the same origin.
+    pub fn add_zone(&mut self, zone: Zone) {
+        self.zones.retain(|existing| existing.origin() != zone.origin());
+        self.zones.push(zone);
+    }
+
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+
+    fn find_zone(&self, name: &Name) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .filter(|zone| name.is_subdomain_of(zone.origin()))
+            .max_by_key(|zone| zone.origin().labels().len())
+    }
+
+    fn lookup(zone: &Zone, question: &Question) -> Response {
+        let mut answers = Vec::new();
+        let mut name = question.qname.clone();
+        loop {
+            if let Some(cut) = Self::zone_cut(zone, &name) {
+                let mut response = Self::referral(zone, &cut);
+                response.answers = answers;
+                return response;
+            }
+
+            let Some(records) = zone.records(&name) else {
+                return Response {
+                    authoritative: true,
+                    answers,
+                    authorities: vec![zone.negative_soa()],
+                    ..Response::new(RCode::NameError)
+                };
+            };
+
+            let cname = records.iter().find_map(|record| match &record.rdata {
+                RData::CNAME(target) => Some((record, target)),
+                _ => None,
+            });
+            if let Some((record, target)) = cname.filter(|_| !matches!(question.qtype, QType::CNAME | QType::ANY)) {
+                answers.push(record.clone());
+                // Targets outside of the zone are left to the client to resolve.
+                if !target.is_subdomain_of(zone.origin()) || answers.len() > MAX_CNAME_CHAIN {
+                    return Response {
+                        authoritative: true,
+                        ..Response::from_answers(answers)
+                    };
+                }
+                name = target.clone();
+                continue;
+            }
+
+            let matching: Vec<&Answer> = records
+                .iter()
+                .filter(|record| record.rtype == question.qtype || question.qtype == QType::ANY)
+                .collect();
+            if matching.is_empty() {
+                // NODATA, the name exists but has no records of the type in question.
+                return Response {
+                    authoritative: true,
+                    answers,
+                    authorities: vec![zone.negative_soa()],
+                    ..Response::new(RCode::NoError)
+                };
+            }
+
+            let additionals = Self::addresses(zone, matching.iter().filter_map(|record| match &record.rdata {
+                RData::NS(target) | RData::MX { exchange: target, .. } => Some(target),
+                _ => None,
+            }));
+            answers.extend(matching.into_iter().cloned());
+            return Response {
+                authoritative: true,
+                additionals,
+                ..Response::from_answers(answers)
+            };
+        }
+    }
+
+    /// The topmost delegation below the zone apex on the way to `name`, if any.
+    fn zone_cut(zone: &Zone, name: &Name) -> Option<Name> {
+        let labels = name.labels();
+        let depth = labels.len() - zone.origin().labels().len();
+        (1..=depth)
+            .map(|below| Name::from_labels(labels[depth - below..].iter().cloned()).expect("suffix of a valid name"))
+            .find(|ancestor| zone.rrset(ancestor, QType::NS).next().is_some())
+    }
+
+    /// A referral to the name servers of the delegation at `cut`, with their glue addresses.
+    fn referral(zone: &Zone, cut: &Name) -> Response {
+        let authorities: Vec<Answer> = zone.rrset(cut, QType::NS).cloned().collect();
+        let additionals = Self::addresses(zone, authorities.iter().filter_map(|record| match &record.rdata {
+            RData::NS(target) => Some(target),
+            _ => None,
+        }));
+        Response {
+            authorities,
+            additionals,
+            ..Response::new(RCode::NoError)
+        }
+    }
+
+    /// The A and AAAA records of `names` which are present in the zone.
+    fn addresses<'a>(zone: &Zone, names: impl Iterator<Item = &'a Name>) -> Vec<Answer> {
+        let mut addresses = Vec::new();
+        for name in names {
+            for record in zone.records(name).unwrap_or_default() {
+                if matches!(record.rtype, QType::A | QType::AAAA) && !addresses.contains(record) {
+                    addresses.push(record.clone());
+                }
+            }
+        }
+        addresses
+    }
+}
+
+impl Resolver for AuthoritativeResolver {
+    fn resolve(&self, question: &Question, _request: &Request) -> Result<Response> {
+        if !matches!(question.qclass, QClass::IN | QClass::Any) {
+            return Ok(Response::new(RCode::Refused));
+        }
+        Ok(match self.find_zone(&question.qname) {
+            Some(zone) => Self::lookup(zone, question),
+            None => Response::new(RCode::Refused),
+        })
+    }
+}
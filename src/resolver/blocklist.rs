@@ -0,0 +1,369 @@
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::message::{Answer, Name, QType, Question, RCode, RData};
+
+use super::{Request, Resolver, Response};
+
+/// Names which hosts-format lists commonly map to local addresses and which must not be blocked.
+const HOSTS_BOILERPLATE: [&str; 9] = [
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-allnodes",
+    "ip6-allrouters",
+];
+
+/// A set of domains, matched on whole labels and case-insensitively.
+///
+/// Lists are read line by line, with `#` (and `!` at the start of a line) starting a comment. Each line holds:
+/// - a plain domain, `ads.example`, matching just that name,
+/// - a wildcard, `*.ads.example`, matching all names below `ads.example` but not the name itself,
+/// - a suffix, `.ads.example` or `||ads.example^`, matching the name and all names below it,
+/// - or an address followed by domains as in a hosts file, `0.0.0.0 ads.example`, matching those names.
+///
+/// Other adblock rules, e.g. with modifiers like `||ads.example^$third-party` or exceptions, are rejected.
+#[derive(Debug, Default, Clone)]
+pub struct DomainList {
+    exact: HashSet<Name>,
+    /// Domains whose subdomains match.
+    wildcards: HashSet<Name>,
+    /// Domains which match along with their subdomains.
+    suffixes: HashSet<Name>,
+}
+
+impl DomainList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the entries of the list file at `path`.
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read domain list {}", path.display()))?;
+        for (number, line) in text.lines().enumerate() {
+            self.add_line(line).with_context(|| format!("{}:{}", path.display(), number + 1))?;
+        }
+        Ok(())
+    }
+
+    /// Adds the entries of a single line of a list.
+    pub fn add_line(&mut self, line: &str) -> Result<()> {
+        if line.trim_start().starts_with('!') {
+            return Ok(());
+        }
+        let line = line.split_once('#').map_or(line, |(content, _)| content);
+        let mut fields = line.split_whitespace();
+        let Some(first) = fields.next() else {
+            return Ok(());
+        };
+
+        if first.parse::<IpAddr>().is_ok() {
+            for name in fields.filter(|name| !HOSTS_BOILERPLATE.iter().any(|skip| name.eq_ignore_ascii_case(skip))) {
+                self.exact.insert(parse_domain(name)?);
+            }
+            return Ok(());
+        }
+        if let Some(extra) = fields.next() {
+            bail!("Unexpected '{extra}' after domain");
+        }
+
+        if let Some(domain) = first.strip_prefix("*.") {
+            self.wildcards.insert(parse_domain(domain)?);
+        } else if let Some(rule) = first.strip_prefix("||") {
+            // Rules with modifiers, like `||ads.example^$third-party`, apply to some requests only.
+            let domain = rule.strip_suffix('^').with_context(|| format!("Unsupported adblock rule '{first}'"))?;
+            self.suffixes.insert(parse_domain(domain)?);
+        } else if first.starts_with("@@") || first.contains(['|', '^', '$']) {
+            bail!("Unsupported adblock rule '{first}'");
+        } else if let Some(domain) = first.strip_prefix('.') {
+            self.suffixes.insert(parse_domain(domain)?);
+        } else {
+            self.exact.insert(parse_domain(first)?);
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.wildcards.is_empty() && self.suffixes.is_empty()
+    }
+
+    /// Whether `name` matches any entry of the list.
+    pub fn contains(&self, name: &Name) -> bool {
+        if self.exact.contains(name) || self.suffixes.contains(name) {
+            return true;
+        }
+        let labels = name.labels();
+        (1..labels.len()).any(|skip| {
+            let ancestor = Name::from_labels(labels[skip..].iter().cloned()).expect("suffix of a valid name");
+            self.wildcards.contains(&ancestor) || self.suffixes.contains(&ancestor)
+        })
+    }
+}
+
+fn parse_domain(text: &str) -> Result<Name> {
+    if text.contains(['|', '^', '$', '/', '*']) {
+        bail!("Invalid domain '{text}'");
+    }
+    let name: Name = text.parse().with_context(|| format!("Invalid domain '{text}'"))?;
+    if name.is_root() {
+        bail!("The root domain cannot be listed");
+    }
+    Ok(name)
+}
+
+/// How questions for blocked names are answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockResponse {
+    /// The name does not exist.
+    NxDomain,
+    /// The name exists, but has no records of the type in question.
+    NoData,
+    /// The server refuses to answer.
+    Refused,
+    /// A and AAAA questions are answered with the unspecified address (`0.0.0.0` or `::`), other types with NODATA.
+    Sinkhole,
+}
+
+impl FromStr for BlockResponse {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "nxdomain" => Self::NxDomain,
+            "nodata" => Self::NoData,
+            "refused" => Self::Refused,
+            "sinkhole" => Self::Sinkhole,
+            _ => bail!("Unknown block response '{s}'"),
+        })
+    }
+}
+
+/// Blocks questions for names on a blocklist, passing all others to an inner resolver.
+///
+/// Names on the allowlist are never blocked. Besides the name in question, the targets of CNAME records in
+/// the answer of the inner resolver are checked, so that blocked domains cannot be reached through an alias.
+pub struct BlocklistResolver<R> {
+    inner: R,
+    blocklist: DomainList,
+    allowlist: DomainList,
+    /// How questions for blocked names are answered.
+    pub response: BlockResponse,
+    /// TTL of sinkhole records.
+    pub ttl: i32,
+}
+
+impl<R: Resolver> BlocklistResolver<R> {
+    pub fn new(inner: R, blocklist: DomainList, allowlist: DomainList) -> Self {
+        Self {
+            inner,
+            blocklist,
+            allowlist,
+            response: BlockResponse::NxDomain,
+            ttl: 60,
+        }
+    }
+
+    pub fn is_blocked(&self, name: &Name) -> bool {
+        self.blocklist.contains(name) && !self.allowlist.contains(name)
+    }
+
+    fn blocked(&self, question: &Question) -> Response {
+        match self.response {
+            BlockResponse::NxDomain => Response::new(RCode::NameError),
+            BlockResponse::NoData => Response::new(RCode::NoError),
+            BlockResponse::Refused => Response::new(RCode::Refused),
+            BlockResponse::Sinkhole => {
+                let mut answers = Vec::new();
+                if matches!(question.qtype, QType::A | QType::ANY) {
+                    answers.push(self.sinkhole(question, QType::A, RData::A(Ipv4Addr::UNSPECIFIED)));
+                }
+                if matches!(question.qtype, QType::AAAA | QType::ANY) {
+                    answers.push(self.sinkhole(question, QType::AAAA, RData::AAAA(Ipv6Addr::UNSPECIFIED)));
+                }
+                Response::from_answers(answers)
+            }
+        }
+    }
+
+    fn sinkhole(&self, question: &Question, rtype: QType, rdata: RData) -> Answer {
+        Answer {
+            name: question.qname.clone(),
+            rtype,
+            rclass: question.qclass,
+            ttl: self.ttl,
+            rdata,
+        }
+    }
+}
+
+impl<R: Resolver> Resolver for BlocklistResolver<R> {
+    fn resolve(&self, question: &Question, request: &Request) -> Result<Response> {
+        if self.is_blocked(&question.qname) {
+            return Ok(self.blocked(question));
+        }
+
+        let response = self.inner.resolve(question, request)?;
+        let blocked_alias = response.answers.iter().any(|record| match &record.rdata {
+            RData::CNAME(target) => self.is_blocked(target),
+            _ => false,
+        });
+        if blocked_alias {
+            return Ok(self.blocked(question));
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::message::{Message, QClass};
+    use crate::resolver::Transport;
+
+    /// Answers `alias.example.com` with a CNAME to `tracker.ads.example` and every other name with an address.
+    struct Stub;
+
+    impl Resolver for Stub {
+        fn resolve(&self, question: &Question, _request: &Request) -> Result<Response> {
+            let record = |rdata| Answer { name: question.qname.clone(), rtype: question.qtype, rclass: QClass::IN, ttl: 300, rdata };
+            let answer = if question.qname == name("alias.example.com") {
+                Answer { rtype: QType::CNAME, ..record(RData::CNAME(name("tracker.ads.example"))) }
+            } else {
+                record(RData::A(Ipv4Addr::new(192, 0, 2, 1)))
+            };
+            Ok(Response::from_answers(vec![answer]))
+        }
+    }
+
+    fn name(text: &str) -> Name {
+        text.parse().unwrap()
+    }
+
+    fn list(lines: &[&str]) -> DomainList {
+        let mut list = DomainList::new();
+        for line in lines {
+            list.add_line(line).unwrap();
+        }
+        list
+    }
+
+    fn resolver(allowlist: &[&str], response: BlockResponse) -> BlocklistResolver<Stub> {
+        let mut resolver = BlocklistResolver::new(Stub, list(&["||ads.example^"]), list(allowlist));
+        resolver.response = response;
+        resolver
+    }
+
+    fn resolve(resolver: &BlocklistResolver<Stub>, qname: &str, qtype: QType) -> Response {
+        let question = Question { qname: name(qname), qtype, qclass: QClass::IN };
+        let request = Request {
+            client: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            transport: Transport::Udp,
+            message: Message::query(0, question.clone(), true),
+        };
+        resolver.resolve(&question, &request).unwrap()
+    }
+
+    #[test]
+    fn matches_each_line_format() {
+        let list = list(&[
+            "plain.example",
+            "*.wild.example",
+            ".dot.example",
+            "||adblock.example^",
+            "0.0.0.0 hosts.example other.example # comment",
+            "! adblock comment",
+            "",
+        ]);
+        assert!(list.contains(&name("plain.example")));
+        assert!(!list.contains(&name("www.plain.example")));
+        assert!(!list.contains(&name("wild.example")));
+        assert!(list.contains(&name("a.b.wild.example")));
+        for suffix in ["dot.example", "adblock.example"] {
+            assert!(list.contains(&name(suffix)));
+            assert!(list.contains(&name(&format!("www.{suffix}"))));
+        }
+        assert!(list.contains(&name("hosts.example")));
+        assert!(list.contains(&name("other.example")));
+        // Only whole labels match.
+        assert!(!list.contains(&name("notplain.example")));
+        assert!(!list.contains(&name("xdot.example")));
+    }
+
+    #[test]
+    fn skips_hosts_boilerplate() {
+        let list = list(&["127.0.0.1 localhost", "::1 ip6-localhost ip6-loopback", "255.255.255.255 broadcasthost"]);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn rejects_unsupported_lines() {
+        for line in ["||ads.example^$third-party", "||ads.example", "@@||ads.example^", "/banner/*", "ads.example other.example", "."] {
+            assert!(DomainList::new().add_line(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn matches_case_insensitively() {
+        let list = list(&["Ads.Example", "*.TRACKER.example"]);
+        assert!(list.contains(&name("ads.example")));
+        assert!(list.contains(&name("ADS.EXAMPLE")));
+        assert!(list.contains(&name("www.tracker.EXAMPLE")));
+    }
+
+    #[test]
+    fn allowlist_takes_precedence() {
+        let resolver = resolver(&["good.ads.example"], BlockResponse::NxDomain);
+        assert_eq!(resolve(&resolver, "www.ads.example", QType::A).rcode, RCode::NameError);
+        assert_eq!(resolve(&resolver, "good.ads.example", QType::A).answers.len(), 1);
+        assert_eq!(resolve(&resolver, "www.example.com", QType::A).answers.len(), 1);
+    }
+
+    #[test]
+    fn blocks_cname_targets() {
+        let resolver = resolver(&[], BlockResponse::NxDomain);
+        let response = resolve(&resolver, "alias.example.com", QType::A);
+        assert_eq!(response.rcode, RCode::NameError);
+        assert!(response.answers.is_empty());
+        // Unless the target is allowed.
+        let resolver = self::resolver(&["tracker.ads.example"], BlockResponse::NxDomain);
+        assert_eq!(resolve(&resolver, "alias.example.com", QType::A).answers.len(), 1);
+    }
+
+    #[test]
+    fn answers_with_each_block_response() {
+        let response = resolve(&resolver(&[], BlockResponse::NxDomain), "ads.example", QType::A);
+        assert_eq!(response.rcode, RCode::NameError);
+        let response = resolve(&resolver(&[], BlockResponse::NoData), "ads.example", QType::A);
+        assert_eq!((response.rcode, response.answers.len()), (RCode::NoError, 0));
+        let response = resolve(&resolver(&[], BlockResponse::Refused), "ads.example", QType::A);
+        assert_eq!(response.rcode, RCode::Refused);
+
+        let mut sinkhole = resolver(&[], BlockResponse::Sinkhole);
+        sinkhole.ttl = 30;
+        let response = resolve(&sinkhole, "ads.example", QType::A);
+        assert_eq!(response.rcode, RCode::NoError);
+        assert_eq!(response.answers[0].rdata, RData::A(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(response.answers[0].ttl, 30);
+        let response = resolve(&sinkhole, "ads.example", QType::AAAA);
+        assert_eq!(response.answers[0].rdata, RData::AAAA(Ipv6Addr::UNSPECIFIED));
+        let response = resolve(&sinkhole, "ads.example", QType::ANY);
+        assert_eq!(response.answers.len(), 2);
+        let response = resolve(&sinkhole, "ads.example", QType::MX);
+        assert_eq!((response.rcode, response.answers.len()), (RCode::NoError, 0));
+
+        for (text, response) in [("nxdomain", BlockResponse::NxDomain), ("sinkhole", BlockResponse::Sinkhole)] {
+            assert_eq!(text.parse::<BlockResponse>().unwrap(), response);
+        }
+        assert!("blackhole".parse::<BlockResponse>().is_err());
+    }
+}
@@ -0,0 +1,336 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::message::{Name, QClass, QType, Question, RCode, RData};
+
+use super::{Request, Resolver, Response};
+
+/// Number of cached responses kept when no other limit is configured.
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+/// Identifies a cached response.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    name: Name,
+    /// `None` for NXDOMAIN entries, which apply to every type of the name (RFC 2308 section 5).
+    qtype: Option<QType>,
+    qclass: QClass,
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: Response,
+    inserted: Instant,
+    ttl: Duration,
+    /// Position in [`Cache::lru`].
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    entries: HashMap<Key, Entry>,
+    /// Keys ordered from the least recently used.
+    lru: BTreeMap<u64, Key>,
+    tick: u64,
+}
+
+impl Cache {
+    fn get(&mut self, key: &Key, now: Instant) -> Option<Response> {
+        let entry = self.entries.get_mut(key)?;
+        let elapsed = now.duration_since(entry.inserted);
+        if elapsed >= entry.ttl {
+            self.remove(key);
+            return None;
+        }
+
+        self.lru.remove(&entry.last_used);
+        self.tick += 1;
+        entry.last_used = self.tick;
+        self.lru.insert(self.tick, key.clone());
+
+        let elapsed = elapsed.as_secs() as i32;
+        let mut response = entry.response.clone();
+        for record in response.answers.iter_mut().chain(&mut response.authorities).chain(&mut response.additionals) {
+            record.ttl = (record.ttl - elapsed).max(0);
+        }
+        Some(response)
+    }
+
+    fn insert(&mut self, key: Key, response: Response, ttl: Duration, max_entries: usize, now: Instant) {
+        self.remove(&key);
+        if max_entries == 0 {
+            return;
+        }
+        while self.entries.len() >= max_entries {
+            let Some((_, oldest)) = self.lru.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+
+        self.tick += 1;
+        self.lru.insert(self.tick, key.clone());
+        self.entries.insert(key, Entry { response, inserted: now, ttl, last_used: self.tick });
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.last_used);
+        }
+    }
+}
+
+/// Caches the responses of an inner resolver.
+///
+/// Positive responses are kept for the lowest TTL among their records, negative ones (NXDOMAIN and NODATA)
+/// for the TTL derived from the SOA record in the authority section (RFC 2308 section 5).
+/// Cached records are returned with their TTLs decreased by the time spent in the cache.
+/// Once `max_entries` responses are cached, the least recently used one is evicted.
+pub struct CachingResolver<R> {
+    inner: R,
+    max_entries: usize,
+    cache: Mutex<Cache>,
+}
+
+impl<R: Resolver> CachingResolver<R> {
+    pub fn new(inner: R, max_entries: usize) -> Self {
+        Self {
+            inner,
+            max_entries,
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    /// How long `response` may be cached, or `None` if it must not be cached.
+    fn ttl(response: &Response) -> Option<u32> {
+        let records = response.answers.iter().chain(&response.authorities).chain(&response.additionals);
+        let min_ttl = records.map(|record| record.ttl.max(0) as u32).min();
+        match response.rcode {
+            RCode::NoError if !response.answers.is_empty() => min_ttl,
+            RCode::NoError | RCode::NameError => Self::negative_ttl(response),
+            _ => None,
+        }
+    }
+
+    /// The negative caching TTL: the minimum of the SOA record's own TTL and its MINIMUM field.
+    fn negative_ttl(response: &Response) -> Option<u32> {
+        response.authorities.iter().find_map(|record| match record.rdata {
+            RData::SOA { minimum, .. } => Some(minimum.min(record.ttl.max(0) as u32)),
+            _ => None,
+        })
+    }
+}
+
+impl<R: Resolver> Resolver for CachingResolver<R> {
+    fn resolve(&self, question: &Question, request: &Request) -> Result<Response> {
+        let key = Key {
+            name: question.qname.clone(),
+            qtype: Some(question.qtype),
+            qclass: question.qclass,
+        };
+        let nxdomain_key = Key { qtype: None, ..key.clone() };
+
+        let now = Instant::now();
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if let Some(response) = cache.get(&key, now).or_else(|| cache.get(&nxdomain_key, now)) {
+                return Ok(Response { authoritative: false, ..response });
+            }
+        }
+
+        let response = self.inner.resolve(question, request)?;
+        if let Some(ttl) = Self::ttl(&response).filter(|ttl| *ttl > 0) {
+            // After a CNAME the NXDOMAIN is about the end of the chain, not about the name asked for.
+            let aliased = response
+                .answers
+                .iter()
+                .any(|record| record.name == question.qname && record.rtype == QType::CNAME);
+            let key = if response.rcode == RCode::NameError && !aliased { nxdomain_key } else { key };
+            let ttl = Duration::from_secs(ttl as u64);
+            self.cache.lock().unwrap().insert(key, response.clone(), ttl, self.max_entries, now);
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::message::{Answer, Message};
+    use crate::resolver::Transport;
+
+    /// Answers `*.example.com` with an A record of TTL 300 and an AAAA record of TTL 60, `nodata.example.com`
+    /// with NODATA, `alias.example.com` with a CNAME to a missing name and everything else with NXDOMAIN,
+    /// counting the questions it is asked.
+    #[derive(Default)]
+    struct Stub {
+        calls: AtomicUsize,
+    }
+
+    impl Resolver for Stub {
+        fn resolve(&self, question: &Question, _request: &Request) -> Result<Response> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let record = |ttl, rdata| Answer { name: question.qname.clone(), rtype: question.qtype, rclass: QClass::IN, ttl, rdata };
+            let soa = Answer {
+                rtype: QType::SOA,
+                ..record(
+                    3600,
+                    RData::SOA {
+                        mname: "ns.example.com".parse()?,
+                        rname: "admin.example.com".parse()?,
+                        serial: 1,
+                        refresh: 3600,
+                        retry: 600,
+                        expire: 86400,
+                        minimum: 120,
+                    },
+                )
+            };
+
+            if question.qname == "alias.example.com".parse()? {
+                let cname = Answer { rtype: QType::CNAME, ..record(300, RData::CNAME("missing.test".parse()?)) };
+                return Ok(Response {
+                    answers: vec![cname],
+                    authorities: vec![soa],
+                    ..Response::new(RCode::NameError)
+                });
+            }
+            if question.qname == "nodata.example.com".parse()? {
+                return Ok(Response { authorities: vec![soa], ..Response::new(RCode::NoError) });
+            }
+            if !question.qname.labels().ends_with(&[b"example".to_vec(), b"com".to_vec()]) {
+                return Ok(Response { authorities: vec![soa], ..Response::new(RCode::NameError) });
+            }
+            let answer = match question.qtype {
+                QType::AAAA => record(60, RData::AAAA("2001:db8::1".parse()?)),
+                _ => record(300, RData::A(Ipv4Addr::new(192, 0, 2, 1))),
+            };
+            Ok(Response { authoritative: true, ..Response::from_answers(vec![answer]) })
+        }
+    }
+
+    fn question(name: &str, qtype: QType) -> Question {
+        Question { qname: name.parse().unwrap(), qtype, qclass: QClass::IN }
+    }
+
+    fn request(question: &Question) -> Request {
+        let mut message = Message::from_bytes(&[0; 12]).unwrap();
+        message.questions.push(question.clone());
+        Request {
+            client: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            transport: Transport::Udp,
+            message,
+        }
+    }
+
+    fn resolve(resolver: &CachingResolver<Stub>, name: &str, qtype: QType) -> Response {
+        let question = question(name, qtype);
+        resolver.resolve(&question, &request(&question)).unwrap()
+    }
+
+    fn calls(resolver: &CachingResolver<Stub>) -> usize {
+        resolver.inner.calls.load(Ordering::SeqCst)
+    }
+
+    fn cached_ttl(resolver: &CachingResolver<Stub>, name: &str, qtype: Option<QType>) -> Option<Duration> {
+        let key = Key { name: name.parse().unwrap(), qtype, qclass: QClass::IN };
+        resolver.cache.lock().unwrap().entries.get(&key).map(|entry| entry.ttl)
+    }
+
+    #[test]
+    fn answers_repeated_questions_from_the_cache() {
+        let resolver = CachingResolver::new(Stub::default(), 10);
+        assert!(resolve(&resolver, "www.example.com", QType::A).authoritative);
+        let cached = resolve(&resolver, "www.example.com", QType::A);
+        assert_eq!(calls(&resolver), 1);
+        assert!(!cached.authoritative);
+        assert_eq!(cached.answers.len(), 1);
+
+        // Other types of the same name are separate entries.
+        resolve(&resolver, "www.example.com", QType::AAAA);
+        assert_eq!(calls(&resolver), 2);
+        assert_eq!(cached_ttl(&resolver, "www.example.com", Some(QType::AAAA)), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn decreases_ttls_and_expires_entries() {
+        let mut cache = Cache::default();
+        let key = Key { name: "www.example.com".parse().unwrap(), qtype: Some(QType::A), qclass: QClass::IN };
+        let question = question("www.example.com", QType::A);
+        let response = Stub::default().resolve(&question, &request(&question)).unwrap();
+        let now = Instant::now();
+        cache.insert(key.clone(), response, Duration::from_secs(300), 10, now);
+
+        let aged = cache.get(&key, now + Duration::from_millis(10_500)).unwrap();
+        assert_eq!(aged.answers[0].ttl, 290);
+        assert!(cache.get(&key, now + Duration::from_secs(300)).is_none());
+        assert!(cache.entries.is_empty() && cache.lru.is_empty());
+    }
+
+    #[test]
+    fn caches_nxdomain_for_every_type() {
+        let resolver = CachingResolver::new(Stub::default(), 10);
+        assert_eq!(resolve(&resolver, "missing.test", QType::A).rcode, RCode::NameError);
+        let cached = resolve(&resolver, "missing.test", QType::MX);
+        assert_eq!(cached.rcode, RCode::NameError);
+        assert_eq!(cached.authorities.len(), 1);
+        assert_eq!(calls(&resolver), 1);
+        assert_eq!(cached_ttl(&resolver, "missing.test", None), Some(Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn caches_nxdomain_after_a_cname_for_the_type_only() {
+        let resolver = CachingResolver::new(Stub::default(), 10);
+        assert_eq!(resolve(&resolver, "alias.example.com", QType::A).rcode, RCode::NameError);
+        assert_eq!(resolve(&resolver, "alias.example.com", QType::A).rcode, RCode::NameError);
+        assert_eq!(calls(&resolver), 1);
+        assert!(cached_ttl(&resolver, "alias.example.com", None).is_none());
+
+        // The alias itself exists, so other types are asked again.
+        resolve(&resolver, "alias.example.com", QType::CNAME);
+        assert_eq!(calls(&resolver), 2);
+    }
+
+    #[test]
+    fn caches_nodata_for_the_soa_minimum() {
+        let resolver = CachingResolver::new(Stub::default(), 10);
+        assert_eq!(resolve(&resolver, "nodata.example.com", QType::A).rcode, RCode::NoError);
+        assert!(resolve(&resolver, "nodata.example.com", QType::A).answers.is_empty());
+        assert_eq!(calls(&resolver), 1);
+        assert_eq!(cached_ttl(&resolver, "nodata.example.com", Some(QType::A)), Some(Duration::from_secs(120)));
+
+        // NODATA only applies to the type asked for.
+        resolve(&resolver, "nodata.example.com", QType::TXT);
+        assert_eq!(calls(&resolver), 2);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let resolver = CachingResolver::new(Stub::default(), 2);
+        resolve(&resolver, "a.example.com", QType::A);
+        resolve(&resolver, "b.example.com", QType::A);
+        // Using `a` makes `b` the least recently used entry.
+        resolve(&resolver, "a.example.com", QType::A);
+        resolve(&resolver, "c.example.com", QType::A);
+        assert_eq!(calls(&resolver), 3);
+
+        assert!(cached_ttl(&resolver, "a.example.com", Some(QType::A)).is_some());
+        assert!(cached_ttl(&resolver, "b.example.com", Some(QType::A)).is_none());
+        assert!(cached_ttl(&resolver, "c.example.com", Some(QType::A)).is_some());
+        resolve(&resolver, "b.example.com", QType::A);
+        assert_eq!(calls(&resolver), 4);
+    }
+
+    #[test]
+    fn caches_nothing_without_entries() {
+        let resolver = CachingResolver::new(Stub::default(), 0);
+        resolve(&resolver, "www.example.com", QType::A);
+        resolve(&resolver, "www.example.com", QType::A);
+        assert_eq!(calls(&resolver), 2);
+    }
+}
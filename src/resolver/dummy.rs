@@ -1,19 +1,19 @@
-use crate::message::{Answer, Question};
+use crate::message::{Answer, QType, Question, RData};
+use std::net::Ipv4Addr;
 use anyhow::Result;
 
-use super::Resolver;
+use super::{Request, Resolver, Response};
 
 pub struct DummyResolver;
 
 impl Resolver for DummyResolver {
-    fn resolve(&self, question: &Question, _msg: &[u8]) -> Result<Vec<Answer>> {
-        Ok(vec![Answer {
+    fn resolve(&self, question: &Question, _request: &Request) -> Result<Response> {
+        Ok(Response::from_answers(vec![Answer {
             name: question.qname.clone(),
-            rtype: question.qtype,
+            rtype: QType::A,
             rclass: question.qclass,
             ttl: 60,
-            rdlength: 4,
-            rdata: vec![0x08, 0x08, 0x08, 0x08],
-        }])
+            rdata: RData::A(Ipv4Addr::new(8, 8, 8, 8)),
+        }]))
     }
 }
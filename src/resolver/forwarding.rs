@@ -1,53 +1,472 @@
-use anyhow::{Context, Result};
-use std::net::UdpSocket;
+use anyhow::{bail, ensure, Context, Result};
+use rand::seq::SliceRandom;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::message::{Answer, Header, Message, Opcode, Question, RCode};
+use crate::message::{Message, Question};
 
-use super::Resolver;
+use super::upstream::{connect_tcp, query_tcp, query_udp, RetryPolicy, UpstreamError};
+use super::{Request, Resolver, Response};
 
-pub struct ForwardingResolver(UdpSocket);
+/// How the upstream to ask first is picked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Always start with the first upstream, the others are only asked when it fails.
+    Failover,
+    /// Start with the next upstream for every query.
+    RoundRobin,
+    /// Start with a random upstream.
+    Random,
+    /// Start with the upstream with the lowest smoothed round trip time.
+    Fastest,
+}
+
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "failover" => Self::Failover,
+            "round-robin" => Self::RoundRobin,
+            "random" => Self::Random,
+            "fastest" => Self::Fastest,
+            _ => bail!("Unknown upstream strategy '{s}'"),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    /// Smoothed round trip time, computed as for TCP (RFC 6298). Failures count as slow responses.
+    srtt: Option<Duration>,
+    consecutive_failures: u32,
+    /// Set while the upstream is marked down: the time of the next probe. Until a probe succeeds,
+    /// the upstream is only asked when all others failed.
+    down_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Upstream {
+    addr: SocketAddr,
+    health: Mutex<Health>,
+    /// An idle TCP connection kept open for reuse.
+    connection: Mutex<Option<TcpStream>>,
+}
+
+/// Forwards every question to one of the configured upstream resolvers.
+///
+/// The response of the upstream is passed through as is: its response code, flags and all record sections.
+/// Truncated UDP responses are replaced by asking the same upstream again over TCP.
+///
+/// When an upstream fails, the next one is asked. After `max_failures` consecutive failures an upstream
+/// is marked down and only asked when all others fail. Every `down_time` it is probed in the background
+/// with a copy of a client question, without holding up the client; a response marks it up again.
+pub struct ForwardingResolver {
+    upstreams: Vec<Arc<Upstream>>,
+    next: AtomicUsize,
+    /// How the upstream to ask first is picked.
+    pub strategy: Strategy,
+    /// How long to wait for each upstream to respond and how often to ask it again.
+    /// The deadline applies to the whole query, across all upstreams.
+    pub retry: RetryPolicy,
+    /// Number of consecutive failures after which an upstream is marked down.
+    pub max_failures: u32,
+    /// How long an upstream stays marked down.
+    pub down_time: Duration,
+    /// Whether TCP connections to upstreams are kept open and reused for later queries.
+    pub reuse_tcp: bool,
+}
+
+impl Upstream {
+    fn record_success(&self, rtt: Duration) {
+        let mut health = self.health.lock().unwrap();
+        health.srtt = Some(match health.srtt {
+            Some(srtt) => srtt * 7 / 8 + rtt / 8,
+            None => rtt,
+        });
+        health.consecutive_failures = 0;
+        health.down_until = None;
+    }
+
+    /// Counts a failure, which also makes the upstream look slow: its smoothed round trip time is doubled,
+    /// to at least `timeout`, so that the `Fastest` strategy stops asking it first.
+    fn record_failure(&self, timeout: Duration, max_failures: u32, down_time: Duration) {
+        let mut health = self.health.lock().unwrap();
+        health.srtt = Some(health.srtt.map_or(timeout, |srtt| (srtt * 2).max(timeout)));
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= max_failures && health.down_until.is_none() {
+            health.down_until = Some(Instant::now() + down_time);
+        }
+    }
+}
 
 impl ForwardingResolver {
-    pub fn new(addr: &str) -> Result<Self> {
-        let socket = UdpSocket::bind("127.0.0.1:0").context("Cannot bind socket for forwarding")?;
-        socket
-            .connect(addr)
-            .context("Failed to connect socket to given addr")?;
-        Ok(Self(socket))
+    pub fn new<A: ToSocketAddrs>(addrs: &[A]) -> Result<Self> {
+        let mut upstreams = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            let addr = addr
+                .to_socket_addrs()
+                .context("Failed to resolve forwarding address")?
+                .next()
+                .context("Forwarding address resolved to no address")?;
+            upstreams.push(Arc::new(Upstream {
+                addr,
+                health: Mutex::new(Health::default()),
+                connection: Mutex::new(None),
+            }));
+        }
+        ensure!(!upstreams.is_empty(), "At least one forwarding address is required");
+
+        Ok(Self {
+            upstreams,
+            next: AtomicUsize::new(0),
+            strategy: Strategy::Failover,
+            retry: RetryPolicy::default(),
+            max_failures: 3,
+            down_time: Duration::from_secs(30),
+            reuse_tcp: false,
+        })
+    }
+
+    /// The upstreams in the order they should be asked, those marked down last.
+    fn candidates(&self) -> Vec<&Upstream> {
+        let mut order: Vec<&Upstream> = self.upstreams.iter().map(Arc::as_ref).collect();
+        match self.strategy {
+            Strategy::Failover => {}
+            Strategy::RoundRobin => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed) % order.len();
+                order.rotate_left(start);
+            }
+            Strategy::Random => order.shuffle(&mut rand::thread_rng()),
+            // Upstreams without a measurement come first, so that they get one.
+            Strategy::Fastest => order.sort_by_key(|upstream| upstream.health.lock().unwrap().srtt.unwrap_or_default()),
+        }
+
+        order.sort_by_key(|upstream| upstream.health.lock().unwrap().down_until.is_some());
+        order
+    }
+
+    /// Sends `question` to every upstream marked down whose next probe is due, each in a thread of its own.
+    fn probe_down_upstreams(&self, question: &Question) {
+        let now = Instant::now();
+        for upstream in &self.upstreams {
+            {
+                let mut health = upstream.health.lock().unwrap();
+                match health.down_until {
+                    Some(until) if until <= now => health.down_until = Some(now + self.down_time),
+                    _ => continue,
+                }
+            }
+
+            let upstream = Arc::clone(upstream);
+            let query = Message::query(rand::random(), question.clone(), true);
+            let retry = RetryPolicy { retries: 0, ..self.retry };
+            let (max_failures, down_time) = (self.max_failures, self.down_time);
+            thread::spawn(move || {
+                let start = Instant::now();
+                match query_udp(upstream.addr, &query, &retry, start + retry.deadline) {
+                    Ok(_) => upstream.record_success(start.elapsed()),
+                    Err(_) => upstream.record_failure(retry.attempt_timeout, max_failures, down_time),
+                }
+            });
+        }
+    }
+
+    /// Asks `upstream` over UDP, falling back to TCP if the response is truncated.
+    fn query(&self, upstream: &Upstream, query: &Message, deadline: Instant) -> Result<Message, UpstreamError> {
+        let response = query_udp(upstream.addr, query, &self.retry, deadline)?;
+        if !response.header.truncation {
+            return Ok(response);
+        }
+        self.query_tcp(upstream, query, deadline)
+    }
+
+    fn query_tcp(&self, upstream: &Upstream, query: &Message, deadline: Instant) -> Result<Message, UpstreamError> {
+        if self.reuse_tcp {
+            let idle = upstream.connection.lock().unwrap().take();
+            if let Some(mut stream) = idle {
+                // The upstream may have closed the idle connection in the meantime, so failures fall through to a new one.
+                if let Ok(response) = query_tcp(&mut stream, upstream.addr, query, deadline) {
+                    *upstream.connection.lock().unwrap() = Some(stream);
+                    return Ok(response);
+                }
+            }
+        }
+
+        let mut stream = connect_tcp(upstream.addr, deadline)?;
+        let response = query_tcp(&mut stream, upstream.addr, query, deadline)?;
+        if self.reuse_tcp {
+            *upstream.connection.lock().unwrap() = Some(stream);
+        }
+        Ok(response)
     }
 }
 
 impl Resolver for ForwardingResolver {
-    fn resolve(&self, question: &Question, msg: &[u8]) -> Result<Vec<Answer>> {
-        let query = Message {
-            header: Header {
-                id: 0,
-                is_reply: false,
-                opcode: Opcode::Query,
-                authoritative: false,
-                truncation: false,
-                recursion_desired: false,
-                recursion_available: false,
-                rcode: RCode::NoError,
-                question_count: 1,
-                answer_count: 0,
-                authority_count: 0,
-                additional_count: 0,
-            },
-            questions: vec![question.with_resolved_name(msg)],
-            answers: Vec::new(),
+    fn resolve(&self, question: &Question, request: &Request) -> Result<Response> {
+        let recursion_desired = request.message.header.recursion_desired;
+        let query = Message::query(rand::random(), question.clone(), recursion_desired);
+        let deadline = Instant::now() + self.retry.deadline;
+        self.probe_down_upstreams(question);
+
+        let mut last_error = None;
+        for upstream in self.candidates() {
+            let start = Instant::now();
+            match self.query(upstream, &query, deadline) {
+                Ok(response) => {
+                    upstream.record_success(start.elapsed());
+                    return Ok(Response {
+                        rcode: response.header.rcode,
+                        authoritative: response.header.authoritative,
+                        recursion_available: response.header.recursion_available,
+                        answers: response.answers,
+                        authorities: response.authorities,
+                        additionals: response.additionals,
+                    });
+                }
+                Err(err) => {
+                    let err = anyhow::Error::from(err);
+                    eprintln!("Upstream {} failed: {err:#}", upstream.addr);
+                    upstream.record_failure(self.retry.attempt_timeout, self.max_failures, self.down_time);
+                    last_error = Some(err);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No upstream to forward to")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+    use crate::message::{Answer, QClass, QType, RCode, RData};
+    use crate::resolver::upstream::tests::{answer, reply_to, tcp_upstream, udp_upstream};
+    use crate::resolver::Transport;
+
+    fn resolver(upstreams: &[SocketAddr]) -> ForwardingResolver {
+        let mut resolver = ForwardingResolver::new(upstreams).unwrap();
+        resolver.retry = RetryPolicy {
+            attempt_timeout: Duration::from_millis(50),
+            retries: 0,
+            ..RetryPolicy::default()
         };
-        self.0
-            .send(&query.as_bytes())
-            .context("Failed to send forwarding query")?;
-
-        let mut buf = [0; 512];
-        let size = self
-            .0
-            .recv(&mut buf)
-            .context("Failed to receive data from forwading server")?;
-
-        let response = Message::from_bytes(&buf[0..size]);
-        Ok(response.answers)
+        resolver
+    }
+
+    fn resolve(resolver: &ForwardingResolver) -> Result<Response> {
+        let question = Question { qname: "www.example.com".parse()?, qtype: QType::A, qclass: QClass::IN };
+        let request = Request {
+            client: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            transport: Transport::Udp,
+            message: Message::query(0, question.clone(), true),
+        };
+        resolver.resolve(&question, &request)
+    }
+
+    fn good() -> (SocketAddr, Arc<AtomicUsize>) {
+        udp_upstream(|query| vec![answer(query, Ipv4Addr::new(192, 0, 2, 1))])
+    }
+
+    fn silent() -> (SocketAddr, Arc<AtomicUsize>) {
+        udp_upstream(|_| Vec::new())
+    }
+
+    fn count(queries: &AtomicUsize) -> usize {
+        queries.load(Ordering::SeqCst)
+    }
+
+    fn is_down(resolver: &ForwardingResolver, index: usize) -> bool {
+        resolver.upstreams[index].health.lock().unwrap().down_until.is_some()
+    }
+
+    #[test]
+    fn fails_over_and_marks_upstreams_down() {
+        let (silent, silent_queries) = silent();
+        let (good, good_queries) = good();
+        let mut resolver = resolver(&[silent, good]);
+        resolver.max_failures = 2;
+
+        for _ in 0..2 {
+            assert_eq!(resolve(&resolver).unwrap().answers.len(), 1);
+        }
+        assert!(is_down(&resolver, 0));
+        assert!(!is_down(&resolver, 1));
+
+        // Upstreams marked down are asked last.
+        resolve(&resolver).unwrap();
+        assert_eq!(count(&silent_queries), 2);
+        assert_eq!(count(&good_queries), 3);
+    }
+
+    #[test]
+    fn asks_upstreams_marked_down_when_all_others_fail() {
+        let (first, first_queries) = silent();
+        let (second, second_queries) = silent();
+        let mut resolver = resolver(&[first, second]);
+        resolver.max_failures = 1;
+
+        assert!(resolve(&resolver).is_err());
+        assert!(is_down(&resolver, 0) && is_down(&resolver, 1));
+        assert!(resolve(&resolver).is_err());
+        assert_eq!((count(&first_queries), count(&second_queries)), (2, 2));
+    }
+
+    #[test]
+    fn probes_upstreams_marked_down() {
+        let answering = Arc::new(AtomicBool::new(false));
+        let switch = Arc::clone(&answering);
+        let (flaky, _) = udp_upstream(move |query| match switch.load(Ordering::SeqCst) {
+            true => vec![answer(query, Ipv4Addr::new(192, 0, 2, 1))],
+            false => Vec::new(),
+        });
+        let (good, _) = good();
+        let mut resolver = resolver(&[flaky, good]);
+        resolver.max_failures = 1;
+        resolver.down_time = Duration::from_millis(100);
+
+        resolve(&resolver).unwrap();
+        assert!(is_down(&resolver, 0));
+
+        answering.store(true, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(150));
+        resolve(&resolver).unwrap();
+        // The probe runs in the background.
+        thread::sleep(Duration::from_millis(100));
+        assert!(!is_down(&resolver, 0));
+    }
+
+    #[test]
+    fn raises_the_round_trip_time_of_failing_upstreams() {
+        let (silent, _) = silent();
+        let (good, _) = good();
+        let resolver = resolver(&[silent, good]);
+        resolve(&resolver).unwrap();
+        let srtt = resolver.upstreams[0].health.lock().unwrap().srtt;
+        assert_eq!(srtt, Some(resolver.retry.attempt_timeout));
+    }
+
+    #[test]
+    fn rotates_upstreams_round_robin() {
+        let (first, first_queries) = good();
+        let (second, second_queries) = good();
+        let mut resolver = resolver(&[first, second]);
+        resolver.strategy = Strategy::RoundRobin;
+
+        for _ in 0..4 {
+            resolve(&resolver).unwrap();
+        }
+        assert_eq!((count(&first_queries), count(&second_queries)), (2, 2));
+    }
+
+    #[test]
+    fn picks_random_upstreams() {
+        let (first, first_queries) = good();
+        let (second, second_queries) = good();
+        let mut resolver = resolver(&[first, second]);
+        resolver.strategy = Strategy::Random;
+
+        for _ in 0..32 {
+            resolve(&resolver).unwrap();
+        }
+        assert!(count(&first_queries) > 0 && count(&second_queries) > 0);
+    }
+
+    #[test]
+    fn prefers_the_fastest_upstream() {
+        let (slow, slow_queries) = udp_upstream(|query| {
+            thread::sleep(Duration::from_millis(20));
+            vec![answer(query, Ipv4Addr::new(192, 0, 2, 1))]
+        });
+        let (fast, fast_queries) = good();
+        let mut resolver = resolver(&[slow, fast]);
+        resolver.strategy = Strategy::Fastest;
+
+        // Both are asked once to measure them, then only the fast one.
+        for _ in 0..8 {
+            resolve(&resolver).unwrap();
+        }
+        assert_eq!((count(&slow_queries), count(&fast_queries)), (1, 7));
+    }
+
+    #[test]
+    fn passes_rcode_flags_and_sections_through() {
+        let (upstream, _) = udp_upstream(|query| {
+            let soa = Answer {
+                name: "example.com".parse().unwrap(),
+                rtype: QType::SOA,
+                rclass: QClass::IN,
+                ttl: 300,
+                rdata: RData::SOA {
+                    mname: "ns.example.com".parse().unwrap(),
+                    rname: "admin.example.com".parse().unwrap(),
+                    serial: 1,
+                    refresh: 3600,
+                    retry: 600,
+                    expire: 86400,
+                    minimum: 300,
+                },
+            };
+            let glue = Answer {
+                name: "ns.example.com".parse().unwrap(),
+                rtype: QType::A,
+                rclass: QClass::IN,
+                ttl: 300,
+                rdata: RData::A(Ipv4Addr::new(192, 0, 2, 53)),
+            };
+            let mut response = reply_to(query, RCode::NameError, Vec::new());
+            response.header.authoritative = true;
+            response.header.recursion_available = true;
+            response.authorities = vec![soa];
+            response.additionals = vec![glue];
+            vec![response]
+        });
+
+        let response = resolve(&resolver(&[upstream])).unwrap();
+        assert_eq!(response.rcode, RCode::NameError);
+        assert!(response.authoritative && response.recursion_available);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities[0].rtype, QType::SOA);
+        assert_eq!(response.additionals[0].rdata, RData::A(Ipv4Addr::new(192, 0, 2, 53)));
+    }
+
+    /// Starts an upstream which sets TC on all UDP responses and answers over TCP on the same port.
+    fn truncating() -> (SocketAddr, Arc<AtomicUsize>) {
+        let (addr, _) = udp_upstream(|query| {
+            let mut response = reply_to(query, RCode::NoError, Vec::new());
+            response.header.truncation = true;
+            vec![response]
+        });
+        tcp_upstream(addr, |query| vec![answer(query, Ipv4Addr::new(192, 0, 2, 1))])
+    }
+
+    #[test]
+    fn retries_truncated_responses_over_tcp() {
+        let (upstream, connections) = truncating();
+        let resolver = resolver(&[upstream]);
+
+        for _ in 0..2 {
+            let response = resolve(&resolver).unwrap();
+            assert_eq!(response.answers[0].rdata, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        }
+        assert_eq!(count(&connections), 2);
+    }
+
+    #[test]
+    fn reuses_tcp_connections() {
+        let (upstream, connections) = truncating();
+        let mut resolver = resolver(&[upstream]);
+        resolver.reuse_tcp = true;
+
+        for _ in 0..3 {
+            assert_eq!(resolve(&resolver).unwrap().answers.len(), 1);
+        }
+        assert_eq!(count(&connections), 1);
     }
 }
@@ -0,0 +1,303 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::message::{Answer, Name, QClass, QType, Question, RData};
+
+use super::{Request, Resolver, Response};
+
+/// The contents of a hosts file.
+#[derive(Debug, Default)]
+struct Hosts {
+    /// Addresses by host name, in the order they appear in the file.
+    addresses: HashMap<Name, Vec<IpAddr>>,
+    /// Host names by reverse lookup name (`in-addr.arpa` or `ip6.arpa`).
+    /// Each address maps to the first name given for it, like the C library does.
+    pointers: HashMap<Name, Name>,
+}
+
+impl Hosts {
+    /// Parses a file in the format of `/etc/hosts`: an address followed by one or more names on each line,
+    /// with `#` starting a comment. Malformed lines are skipped with a warning.
+    fn parse(text: &str, path: &Path) -> Self {
+        let mut hosts = Self::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split_once('#').map_or(line, |(content, _)| content);
+            let mut fields = line.split_whitespace();
+            let Some(address) = fields.next() else {
+                continue;
+            };
+            // Zone indices of link-local IPv6 addresses (`fe80::1%eth0`) mean nothing to remote clients.
+            let address = address.split_once('%').map_or(address, |(address, _)| address);
+            let Ok(address) = address.parse::<IpAddr>() else {
+                eprintln!("{}:{}: invalid address '{address}'", path.display(), number + 1);
+                continue;
+            };
+
+            for name in fields {
+                let Ok(name) = name.parse::<Name>() else {
+                    eprintln!("{}:{}: invalid host name '{name}'", path.display(), number + 1);
+                    continue;
+                };
+                hosts.pointers.entry(reverse_name(address)).or_insert_with(|| name.clone());
+                let addresses = hosts.addresses.entry(name).or_default();
+                if !addresses.contains(&address) {
+                    addresses.push(address);
+                }
+            }
+        }
+        hosts
+    }
+}
+
+/// The name for reverse lookups of `address`, e.g. `4.3.2.1.in-addr.arpa` for `1.2.3.4`.
+fn reverse_name(address: IpAddr) -> Name {
+    let labels: Vec<String> = match address {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets().into_iter().rev().map(|octet| octet.to_string());
+            octets.chain(["in-addr", "arpa"].map(String::from)).collect()
+        }
+        IpAddr::V6(ip) => {
+            let nibbles = ip.octets().into_iter().rev().flat_map(|octet| [octet & 0xf, octet >> 4]);
+            let nibbles = nibbles.map(|nibble| format!("{nibble:x}"));
+            nibbles.chain(["ip6", "arpa"].map(String::from)).collect()
+        }
+    };
+    Name::from_labels(labels).expect("reverse names are short enough")
+}
+
+#[derive(Debug)]
+struct State {
+    hosts: Hosts,
+    /// Modification time of the file when it was last read.
+    modified: Option<SystemTime>,
+    /// When the modification time was last looked at.
+    checked: Instant,
+}
+
+/// How often the modification time of the file is looked at.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Answers A, AAAA and PTR questions from a hosts file, passing everything else to an inner resolver.
+///
+/// For names listed in the file, A and AAAA questions are answered from it only, so that entries override
+/// the inner resolver; a name with only IPv4 addresses gets an empty answer for AAAA and vice versa.
+/// PTR records are synthesized for the listed addresses. The file is read again when its modification
+/// time changes, checked at most once a second; if reading fails, the previous contents are kept.
+pub struct HostsResolver<R> {
+    inner: R,
+    path: PathBuf,
+    /// TTL of the records answered from the file.
+    pub ttl: i32,
+    state: Mutex<State>,
+}
+
+impl<R: Resolver> HostsResolver<R> {
+    /// Reads the hosts file at `path`, failing if it cannot be read.
+    pub fn new(path: impl Into<PathBuf>, inner: R) -> Result<Self> {
+        let path = path.into();
+        let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+        let text = fs::read_to_string(&path).with_context(|| format!("Failed to read hosts file {}", path.display()))?;
+        Ok(Self {
+            inner,
+            ttl: 60,
+            state: Mutex::new(State {
+                hosts: Hosts::parse(&text, &path),
+                modified,
+                checked: Instant::now(),
+            }),
+            path,
+        })
+    }
+
+    /// Reads the file again if it was modified since it was last read.
+    ///
+    /// The modification time is looked at no more than once per [`RELOAD_INTERVAL`], so that the lock
+    /// shared by all workers is not held for a system call on every question.
+    fn reload_if_modified(&self, state: &mut State) {
+        let now = Instant::now();
+        if now.duration_since(state.checked) < RELOAD_INTERVAL {
+            return;
+        }
+        state.checked = now;
+        let modified = fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok();
+        if modified.is_none() || modified == state.modified {
+            return;
+        }
+        match fs::read_to_string(&self.path) {
+            Ok(text) => {
+                state.hosts = Hosts::parse(&text, &self.path);
+                state.modified = modified;
+            }
+            Err(err) => eprintln!("Failed to reload hosts file {}: {err}", self.path.display()),
+        }
+    }
+
+    fn record(&self, name: &Name, rtype: QType, rdata: RData) -> Answer {
+        Answer {
+            name: name.clone(),
+            rtype,
+            rclass: QClass::IN,
+            ttl: self.ttl,
+            rdata,
+        }
+    }
+
+    /// Answers `question` from the file, or returns `None` if the file has nothing to say about it.
+    fn lookup(&self, question: &Question) -> Option<Response> {
+        let mut state = self.state.lock().unwrap();
+        self.reload_if_modified(&mut state);
+
+        let name = &question.qname;
+        let answers: Vec<Answer> = match question.qtype {
+            QType::A | QType::AAAA | QType::ANY => {
+                let addresses = state.hosts.addresses.get(name)?;
+                let rdata = addresses.iter().filter_map(|address| match (address, question.qtype) {
+                    (IpAddr::V4(ip), QType::A | QType::ANY) => Some((QType::A, RData::A(*ip))),
+                    (IpAddr::V6(ip), QType::AAAA | QType::ANY) => Some((QType::AAAA, RData::AAAA(*ip))),
+                    _ => None,
+                });
+                rdata.map(|(rtype, rdata)| self.record(name, rtype, rdata)).collect()
+            }
+            QType::PTR => {
+                let host = state.hosts.pointers.get(name)?;
+                vec![self.record(name, QType::PTR, RData::PTR(host.clone()))]
+            }
+            _ => return None,
+        };
+        // This server has no authority over the names in the file, so the AA bit stays unset.
+        Some(Response::from_answers(answers))
+    }
+}
+
+impl<R: Resolver> Resolver for HostsResolver<R> {
+    fn resolve(&self, question: &Question, request: &Request) -> Result<Response> {
+        if !matches!(question.qclass, QClass::IN | QClass::Any) {
+            return self.inner.resolve(question, request);
+        }
+        match self.lookup(question) {
+            Some(response) => Ok(response),
+            None => self.inner.resolve(question, request),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    use super::*;
+    use crate::message::{Message, RCode};
+    use crate::resolver::{DummyResolver, Transport};
+
+    const HOSTS: &str = "\
+# Comments and malformed lines are skipped.
+127.0.0.1   localhost
+192.0.2.1   www.example.com www web   # aliases
+192.0.2.2   www.example.com
+2001:db8::1 v6.example.com
+fe80::1%eth0 link.example.com
+192.0.2.1   later.example.com
+not-an-address broken.example.com
+";
+
+    /// Writes `text` to a hosts file of its own, named after `test`.
+    fn hosts_file(test: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("hosts-{test}-{}", std::process::id()));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn resolver(test: &str) -> HostsResolver<DummyResolver> {
+        HostsResolver::new(hosts_file(test, HOSTS), DummyResolver).unwrap()
+    }
+
+    fn resolve(resolver: &HostsResolver<DummyResolver>, name: &str, qtype: QType) -> Response {
+        let question = Question { qname: name.parse().unwrap(), qtype, qclass: QClass::IN };
+        let request = Request {
+            client: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            transport: Transport::Udp,
+            message: Message::query(0, question.clone(), true),
+        };
+        resolver.resolve(&question, &request).unwrap()
+    }
+
+    fn rdata(response: &Response) -> Vec<RData> {
+        response.answers.iter().map(|record| record.rdata.clone()).collect()
+    }
+
+    #[test]
+    fn answers_every_name_of_a_line() {
+        let resolver = resolver("aliases");
+        for name in ["www.example.com", "www", "WEB"] {
+            let response = resolve(&resolver, name, QType::A);
+            assert_eq!(response.rcode, RCode::NoError);
+            assert!(!response.authoritative);
+            assert_eq!(response.answers[0].rdata, RData::A(Ipv4Addr::new(192, 0, 2, 1)), "{name}");
+        }
+        // Addresses from several lines add up.
+        let response = resolve(&resolver, "www.example.com", QType::A);
+        assert_eq!(rdata(&response), [RData::A(Ipv4Addr::new(192, 0, 2, 1)), RData::A(Ipv4Addr::new(192, 0, 2, 2))]);
+    }
+
+    #[test]
+    fn answers_ipv6_without_zone_index() {
+        let resolver = resolver("ipv6");
+        let response = resolve(&resolver, "v6.example.com", QType::AAAA);
+        assert_eq!(rdata(&response), [RData::AAAA("2001:db8::1".parse().unwrap())]);
+        let response = resolve(&resolver, "link.example.com", QType::AAAA);
+        assert_eq!(rdata(&response), [RData::AAAA(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1))]);
+    }
+
+    #[test]
+    fn answers_nodata_for_the_missing_address_family() {
+        let resolver = resolver("nodata");
+        let response = resolve(&resolver, "www.example.com", QType::AAAA);
+        assert_eq!(response.rcode, RCode::NoError);
+        assert!(response.answers.is_empty());
+        assert!(resolve(&resolver, "v6.example.com", QType::A).answers.is_empty());
+    }
+
+    #[test]
+    fn passes_other_questions_on() {
+        let resolver = resolver("inner");
+        // The inner resolver answers everything with 8.8.8.8.
+        let inner = [RData::A(Ipv4Addr::new(8, 8, 8, 8))];
+        assert_eq!(rdata(&resolve(&resolver, "other.example.com", QType::A)), inner);
+        assert_eq!(rdata(&resolve(&resolver, "broken.example.com", QType::A)), inner);
+        assert_eq!(rdata(&resolve(&resolver, "www.example.com", QType::MX)), inner);
+    }
+
+    #[test]
+    fn synthesizes_pointers_for_the_first_name() {
+        let resolver = resolver("ptr");
+        let response = resolve(&resolver, "1.2.0.192.in-addr.arpa", QType::PTR);
+        assert_eq!(rdata(&response), [RData::PTR("www.example.com".parse().unwrap())]);
+
+        let v6 = "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa";
+        let response = resolve(&resolver, v6, QType::PTR);
+        assert_eq!(rdata(&response), [RData::PTR("v6.example.com".parse().unwrap())]);
+    }
+
+    #[test]
+    fn reloads_when_modified() {
+        let path = hosts_file("reload", "192.0.2.1 www.example.com\n");
+        let resolver = HostsResolver::new(&path, DummyResolver).unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+
+        fs::write(&path, "192.0.2.9 www.example.com\n").unwrap();
+        File::options().write(true).open(&path).unwrap().set_modified(modified + Duration::from_secs(5)).unwrap();
+        // The modification time is only looked at once a second.
+        assert_eq!(rdata(&resolve(&resolver, "www.example.com", QType::A)), [RData::A(Ipv4Addr::new(192, 0, 2, 1))]);
+
+        resolver.state.lock().unwrap().checked -= RELOAD_INTERVAL;
+        assert_eq!(rdata(&resolve(&resolver, "www.example.com", QType::A)), [RData::A(Ipv4Addr::new(192, 0, 2, 9))]);
+        fs::remove_file(path).unwrap();
+    }
+}
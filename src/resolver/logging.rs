@@ -0,0 +1,34 @@
+use anyhow::Result;
+use std::time::Instant;
+
+use crate::message::Question;
+
+use super::{Request, Resolver, Response};
+
+/// Logs every question, together with the outcome of resolving it by the inner resolver, to stderr.
+pub struct LoggingResolver<R> {
+    inner: R,
+}
+
+impl<R: Resolver> LoggingResolver<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+}
+
+impl<R: Resolver> Resolver for LoggingResolver<R> {
+    fn resolve(&self, question: &Question, request: &Request) -> Result<Response> {
+        let start = Instant::now();
+        let result = self.inner.resolve(question, request);
+        let elapsed = start.elapsed().as_millis();
+        let Question { qname, qtype, .. } = question;
+        match &result {
+            Ok(response) => eprintln!(
+                "{} {:?} {qname} {qtype:?}: {:?}, {} answers in {elapsed} ms",
+                request.client, request.transport, response.rcode, response.answers.len(),
+            ),
+            Err(err) => eprintln!("{} {:?} {qname} {qtype:?}: failed in {elapsed} ms: {err:#}", request.client, request.transport),
+        }
+        result
+    }
+}
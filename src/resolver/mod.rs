@@ -1,12 +1,68 @@
-use crate::message::{Answer, Question};
+use crate::message::Question;
 use anyhow::Result;
+use std::sync::Arc;
+
+mod acl;
+pub use acl::{AclResolver, Network};
+
+mod authoritative;
+pub use authoritative::AuthoritativeResolver;
+
+mod blocklist;
+pub use blocklist::{BlockResponse, BlocklistResolver, DomainList};
+
+mod caching;
+pub use caching::{CachingResolver, DEFAULT_MAX_ENTRIES};
 
 mod dummy;
 pub use dummy::DummyResolver;
 
 mod forwarding;
-pub use forwarding::ForwardingResolver;
+pub use forwarding::{ForwardingResolver, Strategy};
+
+mod hosts;
+pub use hosts::HostsResolver;
+
+mod logging;
+pub use logging::LoggingResolver;
+
+mod pipeline;
+pub use pipeline::{Layer, Pipeline, Refuse};
+
+mod recursive;
+pub use recursive::{RecursiveResolver, ROOT_HINTS};
+
+mod request;
+pub use request::{Request, Transport};
+
+mod response;
+pub use response::Response;
+
+mod rewrite;
+pub use rewrite::RewriteResolver;
+
+mod routing;
+pub use routing::RoutingResolver;
+
+mod upstream;
+pub use upstream::{RetryPolicy, UpstreamError};
+
+/// Resolves questions on behalf of the server.
+///
+/// Resolvers are shared by all threads serving requests, so they must be `Send + Sync`.
+pub trait Resolver: Send + Sync {
+    /// Resolves a single question of `request`.
+    fn resolve(&self, question: &Question, request: &Request) -> Result<Response>;
+}
+
+impl<R: Resolver + ?Sized> Resolver for Box<R> {
+    fn resolve(&self, question: &Question, request: &Request) -> Result<Response> {
+        (**self).resolve(question, request)
+    }
+}
 
-pub trait Resolver {
-    fn resolve(&self, question: &Question, msg: &[u8]) -> Result<Vec<Answer>>;
+impl<R: Resolver + ?Sized> Resolver for Arc<R> {
+    fn resolve(&self, question: &Question, request: &Request) -> Result<Response> {
+        (**self).resolve(question, request)
+    }
 }
@@ -0,0 +1,149 @@
+use anyhow::Result;
+
+use crate::message::{Question, RCode};
+
+use super::{Request, Resolver, Response};
+
+/// A stage of a [`Pipeline`].
+///
+/// A layer is built around the resolver made of the stages after it, its `next` resolver. Like the
+/// wrapping resolvers of this module, the resolver it builds may answer a question itself, pass a modified
+/// question or request on to `next`, modify the response of `next`, or just pass the question on.
+pub trait Layer {
+    /// Builds the resolver of this stage, passing questions it does not answer itself on to `next`.
+    fn wrap(self: Box<Self>, next: Box<dyn Resolver>) -> Result<Box<dyn Resolver>>;
+}
+
+impl<F> Layer for F
+where
+    F: FnOnce(Box<dyn Resolver>) -> Result<Box<dyn Resolver>>,
+{
+    fn wrap(self: Box<Self>, next: Box<dyn Resolver>) -> Result<Box<dyn Resolver>> {
+        self(next)
+    }
+}
+
+/// Refuses every question; the end of a pipeline whose layers all passed a question on.
+pub struct Refuse;
+
+impl Resolver for Refuse {
+    fn resolve(&self, _question: &Question, _request: &Request) -> Result<Response> {
+        Ok(Response::new(RCode::Refused))
+    }
+}
+
+/// A chain of layers, each handling questions before the layers added after it.
+///
+/// For example a pipeline of an ACL, a cache and a forwarder checks the client first, then answers from
+/// the cache and only forwards questions missing from it. Questions passed on by the last layer are refused.
+#[derive(Default)]
+pub struct Pipeline {
+    layers: Vec<Box<dyn Layer>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `layer`, which handles questions after all layers added so far.
+    pub fn push(&mut self, layer: impl Layer + 'static) -> &mut Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    /// Appends a layer which answers every question with `resolver` and never passes any on.
+    pub fn push_resolver(&mut self, resolver: impl Resolver + 'static) -> &mut Self {
+        self.push(move |_next| -> Result<Box<dyn Resolver>> { Ok(Box::new(resolver)) })
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Builds the resolver of the whole chain, from the last layer to the first.
+    pub fn build(self) -> Result<Box<dyn Resolver>> {
+        let mut resolver: Box<dyn Resolver> = Box::new(Refuse);
+        for layer in self.layers.into_iter().rev() {
+            resolver = layer.wrap(resolver)?;
+        }
+        Ok(resolver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::message::{Message, QClass, QType};
+    use crate::resolver::{DummyResolver, Transport};
+
+    /// Notes its name in `log`, then passes the question on.
+    struct Tag {
+        name: &'static str,
+        log: Arc<Mutex<Vec<&'static str>>>,
+        next: Box<dyn Resolver>,
+    }
+
+    impl Resolver for Tag {
+        fn resolve(&self, question: &Question, request: &Request) -> Result<Response> {
+            self.log.lock().unwrap().push(self.name);
+            self.next.resolve(question, request)
+        }
+    }
+
+    fn push_tag(pipeline: &mut Pipeline, name: &'static str, log: &Arc<Mutex<Vec<&'static str>>>) {
+        let log = log.clone();
+        pipeline.push(move |next| -> Result<Box<dyn Resolver>> { Ok(Box::new(Tag { name, log, next })) });
+    }
+
+    fn resolve(resolver: &dyn Resolver) -> Response {
+        let question = Question { qname: "www.example.com".parse().unwrap(), qtype: QType::A, qclass: QClass::IN };
+        let request = Request {
+            client: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            transport: Transport::Udp,
+            message: Message::query(0, question.clone(), true),
+        };
+        resolver.resolve(&question, &request).unwrap()
+    }
+
+    #[test]
+    fn runs_layers_in_order_and_refuses_the_rest() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut pipeline = Pipeline::new();
+        push_tag(&mut pipeline, "first", &log);
+        push_tag(&mut pipeline, "second", &log);
+        assert_eq!(pipeline.len(), 2);
+
+        let response = resolve(&*pipeline.build().unwrap());
+        assert_eq!(*log.lock().unwrap(), ["first", "second"]);
+        assert_eq!(response.rcode, RCode::Refused);
+    }
+
+    #[test]
+    fn stops_at_a_resolver() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut pipeline = Pipeline::new();
+        push_tag(&mut pipeline, "before", &log);
+        pipeline.push_resolver(DummyResolver);
+        push_tag(&mut pipeline, "after", &log);
+
+        let response = resolve(&*pipeline.build().unwrap());
+        assert_eq!(*log.lock().unwrap(), ["before"]);
+        assert_eq!(response.rcode, RCode::NoError);
+        assert_eq!(response.answers.len(), 1);
+    }
+
+    #[test]
+    fn empty_pipeline_refuses() {
+        let pipeline = Pipeline::new();
+        assert!(pipeline.is_empty());
+        assert_eq!(resolve(&*pipeline.build().unwrap()).rcode, RCode::Refused);
+    }
+}
@@ -0,0 +1,563 @@
+use anyhow::{bail, ensure, Result};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Instant;
+
+use crate::message::{Message, Name, QClass, QType, Question, RCode, RData};
+
+use super::upstream::{connect_tcp, query_tcp, query_udp, RetryPolicy, UpstreamError};
+use super::{Request, Resolver, Response};
+
+/// IPv4 addresses of the root servers `a.root-servers.net` to `m.root-servers.net`.
+pub const ROOT_HINTS: [Ipv4Addr; 13] = [
+    Ipv4Addr::new(198, 41, 0, 4),
+    Ipv4Addr::new(170, 247, 170, 2),
+    Ipv4Addr::new(192, 33, 4, 12),
+    Ipv4Addr::new(199, 7, 91, 13),
+    Ipv4Addr::new(192, 203, 230, 10),
+    Ipv4Addr::new(192, 5, 5, 241),
+    Ipv4Addr::new(192, 112, 36, 4),
+    Ipv4Addr::new(198, 97, 190, 53),
+    Ipv4Addr::new(192, 36, 148, 17),
+    Ipv4Addr::new(192, 58, 128, 30),
+    Ipv4Addr::new(193, 0, 14, 129),
+    Ipv4Addr::new(199, 7, 83, 42),
+    Ipv4Addr::new(202, 12, 27, 33),
+];
+
+/// Limits how much work a single client question may cause.
+struct Budget {
+    queries: usize,
+    deadline: Instant,
+}
+
+/// Resolves questions itself by walking the delegation tree, starting from the root servers.
+///
+/// Referrals are followed using the glue records sent along with them; addresses of name servers
+/// outside of the delegating zone are resolved with nested lookups. CNAMEs are chased from the root again.
+pub struct RecursiveResolver {
+    /// Addresses of the root servers.
+    pub root_hints: Vec<SocketAddr>,
+    /// Port used for name servers learned from referrals.
+    pub port: u16,
+    /// How long to wait for each server to respond and how often to ask again.
+    /// The deadline applies to the whole resolution of a client question.
+    pub retry: RetryPolicy,
+    /// Maximum nesting of name server address lookups and CNAME restarts.
+    pub max_depth: usize,
+    /// Maximum number of queries sent to resolve a single question.
+    pub max_queries: usize,
+}
+
+impl Default for RecursiveResolver {
+    fn default() -> Self {
+        Self::new(ROOT_HINTS.iter().map(|ip| SocketAddr::from((*ip, 53))).collect())
+    }
+}
+
+impl RecursiveResolver {
+    pub fn new(root_hints: Vec<SocketAddr>) -> Self {
+        Self {
+            root_hints,
+            port: 53,
+            // Instead of asking the same server again, the other servers of the zone are tried.
+            retry: RetryPolicy {
+                retries: 0,
+                ..RetryPolicy::default()
+            },
+            max_depth: 8,
+            max_queries: 64,
+        }
+    }
+
+    fn lookup(&self, name: &Name, qtype: QType, qclass: QClass, depth: usize, budget: &mut Budget) -> Result<Response> {
+        ensure!(depth <= self.max_depth, "Maximum recursion depth exceeded while resolving {name}");
+
+        let question = Question { qname: name.clone(), qtype, qclass };
+        let mut zone = Name::root();
+        let mut servers = self.root_hints.clone();
+        loop {
+            let response = self.query(&question, &servers, &zone, budget)?;
+            if response.header.rcode == RCode::NameError {
+                return Ok(Response {
+                    authorities: response.authorities,
+                    ..Response::new(RCode::NameError)
+                });
+            }
+
+            if !response.answers.is_empty() {
+                return self.answer(&question, response, depth, budget);
+            }
+
+            let Some((child, ns_names)) = Self::referral(&response, name, &zone) else {
+                // NODATA, the authority section carries the SOA for negative caching.
+                return Ok(Response {
+                    authorities: response.authorities,
+                    ..Response::new(RCode::NoError)
+                });
+            };
+
+            servers = self.server_addresses(&response, &ns_names, &zone, qclass, depth, budget)?;
+            ensure!(!servers.is_empty(), "No usable name server address for zone {child}");
+            zone = child;
+        }
+    }
+
+    /// Sends `question` to each of `servers` of `zone` in turn until one of them gives a usable response.
+    fn query(&self, question: &Question, servers: &[SocketAddr], zone: &Name, budget: &mut Budget) -> Result<Message> {
+        let mut last_error = None;
+        for server in servers {
+            ensure!(budget.queries < self.max_queries, "Query limit exceeded while resolving {}", question.qname);
+            budget.queries += 1;
+
+            let query = Message::query(rand::random(), question.clone(), false);
+            let result = self
+                .query_server(*server, &query, budget.deadline)
+                .map_err(anyhow::Error::from)
+                .and_then(|response| Self::check_response(response, *server, &question.qname, zone));
+            match result {
+                Ok(response) => return Ok(response),
+                Err(err) => last_error = Some(err),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No name server to ask for {}", question.qname)))
+    }
+
+    /// Asks `server` over UDP, falling back to TCP if the response is truncated.
+    fn query_server(&self, server: SocketAddr, query: &Message, deadline: Instant) -> Result<Message, UpstreamError> {
+        let response = query_udp(server, query, &self.retry, deadline)?;
+        if !response.header.truncation {
+            return Ok(response);
+        }
+        let mut stream = connect_tcp(server, deadline)?;
+        query_tcp(&mut stream, server, query, deadline)
+    }
+
+    /// Accepts answers, NXDOMAIN, authoritative NODATA and referrals further down from `zone`.
+    ///
+    /// Any other response means that `server` is broken or lame, i.e. not authoritative for the zone
+    /// it was delegated, like a non-authoritative empty response or a referral upwards. Such responses
+    /// count as a failure of the server, so that the other servers of the zone are asked.
+    fn check_response(response: Message, server: SocketAddr, name: &Name, zone: &Name) -> Result<Message> {
+        match response.header.rcode {
+            RCode::NoError | RCode::NameError => {}
+            rcode => bail!("Name server {server} answered {rcode:?} for {name}"),
+        }
+        let lame = response.header.rcode == RCode::NoError
+            && !response.header.authoritative
+            && response.answers.is_empty()
+            && Self::referral(&response, name, zone).is_none();
+        ensure!(!lame, "Name server {server} is lame for {zone}, no answer or referral for {name}");
+        Ok(response)
+    }
+
+    /// Builds the final response out of an answer, chasing CNAMEs not resolved within it.
+    fn answer(&self, question: &Question, response: Message, depth: usize, budget: &mut Budget) -> Result<Response> {
+        let mut target = question.qname.clone();
+        let mut answers = Vec::new();
+        if !matches!(question.qtype, QType::CNAME | QType::ANY) {
+            while let Some((cname, next)) = response.answers.iter().find_map(|record| match &record.rdata {
+                RData::CNAME(next) if record.name == target => Some((record, next)),
+                _ => None,
+            }) {
+                answers.push(cname.clone());
+                target = next.clone();
+                ensure!(answers.len() <= self.max_depth, "CNAME chain of {} is too long", question.qname);
+            }
+        }
+
+        let matching: Vec<_> = response
+            .answers
+            .iter()
+            .filter(|record| record.name == target && (record.rtype == question.qtype || question.qtype == QType::ANY))
+            .cloned()
+            .collect();
+        if !matching.is_empty() || answers.is_empty() {
+            answers.extend(matching);
+            return Ok(Response::from_answers(answers));
+        }
+
+        // The chain leaves the zone of the server, so the target is looked up from the root again.
+        let mut chased = self.lookup(&target, question.qtype, question.qclass, depth + 1, budget)?;
+        answers.append(&mut chased.answers);
+        chased.answers = answers;
+        Ok(chased)
+    }
+
+    /// Extracts the delegated zone and its name servers from a referral.
+    ///
+    /// Only delegations to a zone below `zone` which contains `name` are accepted.
+    fn referral(response: &Message, name: &Name, zone: &Name) -> Option<(Name, Vec<Name>)> {
+        let child = response
+            .authorities
+            .iter()
+            .find(|record| record.rtype == QType::NS)
+            .map(|record| record.name.clone())?;
+        if child.labels().len() <= zone.labels().len() || !child.is_subdomain_of(zone) || !name.is_subdomain_of(&child) {
+            return None;
+        }
+
+        let ns_names = response
+            .authorities
+            .iter()
+            .filter(|record| record.name == child)
+            .filter_map(|record| match &record.rdata {
+                RData::NS(ns) => Some(ns.clone()),
+                _ => None,
+            })
+            .collect();
+        Some((child, ns_names))
+    }
+
+    /// Addresses of the name servers of a referral, from the glue records if possible.
+    ///
+    /// Glue is only trusted for names within `zone`, the zone of the server that sent the referral.
+    fn server_addresses(
+        &self,
+        response: &Message,
+        ns_names: &[Name],
+        zone: &Name,
+        qclass: QClass,
+        depth: usize,
+        budget: &mut Budget,
+    ) -> Result<Vec<SocketAddr>> {
+        let glue: Vec<SocketAddr> = response
+            .additionals
+            .iter()
+            .filter(|record| ns_names.contains(&record.name) && record.name.is_subdomain_of(zone))
+            .filter_map(|record| match record.rdata {
+                RData::A(ip) => Some(IpAddr::V4(ip)),
+                RData::AAAA(ip) => Some(IpAddr::V6(ip)),
+                _ => None,
+            })
+            .map(|ip| SocketAddr::new(ip, self.port))
+            .collect();
+        if !glue.is_empty() {
+            return Ok(glue);
+        }
+
+        let mut last_error = None;
+        for ns in ns_names {
+            match self.lookup(ns, QType::A, qclass, depth + 1, budget) {
+                Ok(response) => {
+                    let addresses: Vec<_> = response
+                        .answers
+                        .iter()
+                        .filter_map(|record| match record.rdata {
+                            RData::A(ip) => Some(SocketAddr::from((ip, self.port))),
+                            _ => None,
+                        })
+                        .collect();
+                    if !addresses.is_empty() {
+                        return Ok(addresses);
+                    }
+                }
+                Err(err) => last_error = Some(err),
+            }
+        }
+        match last_error {
+            Some(err) => Err(err),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+impl Resolver for RecursiveResolver {
+    fn resolve(&self, question: &Question, _request: &Request) -> Result<Response> {
+        let mut budget = Budget {
+            queries: 0,
+            deadline: Instant::now() + self.retry.deadline,
+        };
+        let mut response = self.lookup(&question.qname, question.qtype, question.qclass, 0, &mut budget)?;
+        response.recursion_available = true;
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, UdpSocket};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::message::Answer;
+    use crate::resolver::Transport;
+    use crate::server::Server;
+
+    /// The records of a zone served by an [`Authority`].
+    struct Zone {
+        apex: Name,
+        records: Vec<Answer>,
+    }
+
+    impl Zone {
+        fn new(apex: &str, records: Vec<Answer>) -> Self {
+            let apex: Name = apex.parse().unwrap();
+            let soa = RData::SOA {
+                mname: apex.clone(),
+                rname: apex.clone(),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            };
+            let mut records = records;
+            records.insert(0, Answer { name: apex.clone(), rtype: QType::SOA, rclass: QClass::IN, ttl: 300, rdata: soa });
+            Self { apex, records }
+        }
+
+        fn find(&self, name: &Name, rtype: QType) -> Vec<Answer> {
+            self.records.iter().filter(|record| &record.name == name && record.rtype == rtype).cloned().collect()
+        }
+    }
+
+    /// Answers from its zones like an authoritative server: with referrals below delegations,
+    /// with the records of the name, or negatively with the SOA record of the zone.
+    struct Authority(Vec<Zone>);
+
+    impl Resolver for Authority {
+        fn resolve(&self, question: &Question, _request: &Request) -> Result<Response> {
+            let zone = self
+                .0
+                .iter()
+                .filter(|zone| question.qname.is_subdomain_of(&zone.apex))
+                .max_by_key(|zone| zone.apex.labels().len());
+            let Some(zone) = zone else {
+                return Ok(Response::new(RCode::Refused));
+            };
+
+            let cut = zone
+                .records
+                .iter()
+                .find(|record| record.rtype == QType::NS && record.name != zone.apex && question.qname.is_subdomain_of(&record.name));
+            if let Some(cut) = cut {
+                let authorities = zone.find(&cut.name, QType::NS);
+                let additionals = authorities
+                    .iter()
+                    .flat_map(|record| match &record.rdata {
+                        RData::NS(target) => zone.find(target, QType::A),
+                        _ => Vec::new(),
+                    })
+                    .collect();
+                return Ok(Response { authorities, additionals, ..Response::new(RCode::NoError) });
+            }
+
+            let mut answers = zone.find(&question.qname, question.qtype);
+            if answers.is_empty() {
+                answers = zone.find(&question.qname, QType::CNAME);
+            }
+            if !answers.is_empty() {
+                return Ok(Response { authoritative: true, ..Response::from_answers(answers) });
+            }
+            let exists = zone.records.iter().any(|record| record.name == question.qname);
+            Ok(Response {
+                authoritative: true,
+                authorities: zone.find(&zone.apex, QType::SOA),
+                ..Response::new(if exists { RCode::NoError } else { RCode::NameError })
+            })
+        }
+    }
+
+    /// Answers every question with a referral back to the root, as lame servers do.
+    struct Lame;
+
+    impl Resolver for Lame {
+        fn resolve(&self, _question: &Question, _request: &Request) -> Result<Response> {
+            Ok(Response {
+                authorities: vec![record(".", QType::NS, RData::NS("a.root.".parse()?))],
+                ..Response::new(RCode::NoError)
+            })
+        }
+    }
+
+    fn record(name: &str, rtype: QType, rdata: RData) -> Answer {
+        Answer { name: name.parse().unwrap(), rtype, rclass: QClass::IN, ttl: 3600, rdata }
+    }
+
+    fn ns(name: &str, target: &str) -> Answer {
+        record(name, QType::NS, RData::NS(target.parse().unwrap()))
+    }
+
+    fn a(name: &str, ip: [u8; 4]) -> Answer {
+        record(name, QType::A, RData::A(Ipv4Addr::from(ip)))
+    }
+
+    /// Serves each resolver on its loopback address, all on the same port, which is returned.
+    fn serve(resolvers: Vec<(Ipv4Addr, Arc<dyn Resolver>)>) -> u16 {
+        let mut port = 0;
+        for (ip, resolver) in resolvers {
+            let socket = UdpSocket::bind((ip, port)).unwrap();
+            port = socket.local_addr().unwrap().port();
+            let mut server = Server::new(resolver);
+            server.workers = 2;
+            thread::spawn(move || Arc::new(server).serve_udp(socket));
+        }
+        port
+    }
+
+    /// Starts the stand-ins for the root, `com.` and the servers of `net.` and `example.com.`,
+    /// after the servers on `extra`, and returns a resolver starting from all of them.
+    fn resolver_with(extra: Vec<(Ipv4Addr, Arc<dyn Resolver>)>) -> RecursiveResolver {
+        let root = Zone::new(".", vec![
+            ns(".", "a.root."),
+            a("a.root.", [127, 0, 0, 10]),
+            ns("com.", "ns.com."),
+            a("ns.com.", [127, 0, 0, 11]),
+            ns("net.", "ns.net."),
+            a("ns.net.", [127, 0, 0, 12]),
+        ]);
+        let com = Zone::new("com.", vec![
+            ns("com.", "ns.com."),
+            a("ns.com.", [127, 0, 0, 11]),
+            // The name server of example.com is outside of com, so there is no glue.
+            ns("example.com.", "ns.example.net."),
+        ]);
+        let net = Zone::new("net.", vec![
+            ns("net.", "ns.net."),
+            a("ns.net.", [127, 0, 0, 12]),
+            a("ns.example.net.", [127, 0, 0, 12]),
+            a("host.example.net.", [192, 0, 2, 2]),
+        ]);
+        let example_com = Zone::new("example.com.", vec![
+            ns("example.com.", "ns.example.net."),
+            a("www.example.com.", [192, 0, 2, 1]),
+            record("alias.example.com.", QType::CNAME, RData::CNAME("host.example.net.".parse().unwrap())),
+        ]);
+
+        let hints: Vec<Ipv4Addr> = extra.iter().map(|(ip, _)| *ip).chain([Ipv4Addr::new(127, 0, 0, 10)]).collect();
+        let mut resolvers = extra;
+        resolvers.extend([
+            (Ipv4Addr::new(127, 0, 0, 10), Arc::new(Authority(vec![root])) as Arc<dyn Resolver>),
+            (Ipv4Addr::new(127, 0, 0, 11), Arc::new(Authority(vec![com]))),
+            (Ipv4Addr::new(127, 0, 0, 12), Arc::new(Authority(vec![net, example_com]))),
+        ]);
+        let port = serve(resolvers);
+
+        let mut resolver = RecursiveResolver::new(hints.into_iter().map(|ip| SocketAddr::from((ip, port))).collect());
+        resolver.port = port;
+        resolver.retry.attempt_timeout = Duration::from_millis(500);
+        resolver
+    }
+
+    fn resolver() -> RecursiveResolver {
+        resolver_with(Vec::new())
+    }
+
+    fn resolve(resolver: &RecursiveResolver, name: &str, qtype: QType) -> Result<Response> {
+        let question = Question { qname: name.parse()?, qtype, qclass: QClass::IN };
+        let request = Request {
+            client: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            transport: Transport::Udp,
+            message: Message::query(0, question.clone(), true),
+        };
+        resolver.resolve(&question, &request)
+    }
+
+    fn addresses(response: &Response) -> Vec<Ipv4Addr> {
+        response
+            .answers
+            .iter()
+            .filter_map(|record| match record.rdata {
+                RData::A(ip) => Some(ip),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn follows_referrals_and_looks_up_name_servers_without_glue() {
+        let response = resolve(&resolver(), "www.example.com.", QType::A).unwrap();
+        assert_eq!(response.rcode, RCode::NoError);
+        assert!(response.recursion_available);
+        assert_eq!(addresses(&response), [Ipv4Addr::new(192, 0, 2, 1)]);
+    }
+
+    #[test]
+    fn chases_cnames_into_other_zones() {
+        let response = resolve(&resolver(), "alias.example.com.", QType::A).unwrap();
+        assert_eq!(response.rcode, RCode::NoError);
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(response.answers[0].rdata, RData::CNAME("host.example.net.".parse().unwrap()));
+        assert_eq!(addresses(&response), [Ipv4Addr::new(192, 0, 2, 2)]);
+    }
+
+    #[test]
+    fn returns_nxdomain_with_soa() {
+        let response = resolve(&resolver(), "missing.example.com.", QType::A).unwrap();
+        assert_eq!(response.rcode, RCode::NameError);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities.len(), 1);
+        assert_eq!(response.authorities[0].rtype, QType::SOA);
+        assert_eq!(response.authorities[0].ttl, 300);
+    }
+
+    #[test]
+    fn returns_nodata_with_soa() {
+        let response = resolve(&resolver(), "www.example.com.", QType::AAAA).unwrap();
+        assert_eq!(response.rcode, RCode::NoError);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities.len(), 1);
+        assert_eq!(response.authorities[0].rtype, QType::SOA);
+    }
+
+    #[test]
+    fn skips_lame_and_refusing_servers() {
+        let resolver = resolver_with(vec![
+            (Ipv4Addr::new(127, 0, 0, 13), Arc::new(Lame)),
+            (Ipv4Addr::new(127, 0, 0, 14), Arc::new(Authority(Vec::new()))),
+        ]);
+        let response = resolve(&resolver, "www.example.com.", QType::A).unwrap();
+        assert_eq!(addresses(&response), [Ipv4Addr::new(192, 0, 2, 1)]);
+    }
+
+    #[test]
+    fn fails_when_only_lame_servers_are_left() {
+        let mut resolver = resolver_with(vec![(Ipv4Addr::new(127, 0, 0, 15), Arc::new(Lame))]);
+        resolver.root_hints.truncate(1);
+        let err = resolve(&resolver, "www.example.com.", QType::A).unwrap_err();
+        assert!(err.to_string().contains("lame"), "{err}");
+    }
+
+    #[test]
+    fn limits_the_depth_of_nested_lookups() {
+        let mut resolver = resolver();
+        resolver.max_depth = 0;
+        let err = resolve(&resolver, "www.example.com.", QType::A).unwrap_err();
+        assert!(err.to_string().contains("depth"), "{err}");
+
+        // Names whose servers all come with glue need no nested lookups.
+        let response = resolve(&resolver, "host.example.net.", QType::A).unwrap();
+        assert_eq!(addresses(&response), [Ipv4Addr::new(192, 0, 2, 2)]);
+    }
+
+    #[test]
+    fn limits_the_number_of_queries() {
+        let mut resolver = resolver();
+        resolver.max_queries = 2;
+        let err = resolve(&resolver, "www.example.com.", QType::A).unwrap_err();
+        assert!(err.to_string().contains("Query limit"), "{err}");
+    }
+
+    #[test]
+    fn retries_truncated_responses_over_tcp() {
+        // Too many addresses for a 512 octet UDP response, so the server truncates it.
+        let answers = (0..40).map(|i| a("big.example.", [192, 0, 2, i])).collect();
+        let zone = Zone::new("example.", answers);
+        let tcp = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let socket = UdpSocket::bind(addr).unwrap();
+        let mut server = Server::new(Arc::new(Authority(vec![zone])));
+        server.max_udp_payload = 512;
+        let server = Arc::new(server);
+        let udp_server = server.clone();
+        thread::spawn(move || udp_server.serve_udp(socket));
+        thread::spawn(move || server.serve_tcp(tcp));
+
+        let mut resolver = RecursiveResolver::new(vec![addr]);
+        resolver.retry.attempt_timeout = Duration::from_millis(500);
+        let response = resolve(&resolver, "big.example.", QType::A).unwrap();
+        assert_eq!(addresses(&response).len(), 40);
+    }
+}
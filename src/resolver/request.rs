@@ -0,0 +1,28 @@
+use std::net::SocketAddr;
+
+use crate::message::{Edns, Message};
+
+/// The transport protocol a request arrived over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+/// Context of the request a question is being resolved for.
+#[derive(Debug)]
+pub struct Request {
+    /// Address of the client that sent the request.
+    pub client: SocketAddr,
+    /// Transport protocol the request arrived over.
+    pub transport: Transport,
+    /// The full parsed request.
+    pub message: Message,
+}
+
+impl Request {
+    /// EDNS(0) parameters sent by the client, if any.
+    pub fn edns(&self) -> Option<&Edns> {
+        self.message.edns.as_ref()
+    }
+}
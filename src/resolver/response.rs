@@ -0,0 +1,40 @@
+use crate::message::{Answer, RCode};
+
+/// The outcome of resolving a single question.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    /// Response code to send to the client.
+    pub rcode: RCode,
+    /// Whether the answer comes from an authority for the domain name in question (AA bit).
+    pub authoritative: bool,
+    /// Whether recursive query support is available (RA bit).
+    pub recursion_available: bool,
+    /// Records answering the question.
+    pub answers: Vec<Answer>,
+    /// Records pointing toward an authoritative name server, or the SOA record of negative answers.
+    pub authorities: Vec<Answer>,
+    /// Records which relate to the question, but are not strictly answers for it (e.g. glue).
+    pub additionals: Vec<Answer>,
+}
+
+impl Response {
+    /// A response with the given code and no records.
+    pub fn new(rcode: RCode) -> Self {
+        Self {
+            rcode,
+            authoritative: false,
+            recursion_available: false,
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        }
+    }
+
+    /// A successful response consisting of `answers` only.
+    pub fn from_answers(answers: Vec<Answer>) -> Self {
+        Self {
+            answers,
+            ..Self::new(RCode::NoError)
+        }
+    }
+}
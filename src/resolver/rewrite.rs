@@ -0,0 +1,131 @@
+use anyhow::Result;
+
+use crate::message::{Name, Question, RData};
+
+use super::{Request, Resolver, Response};
+
+/// Replaces the suffix `from` of `name` by `to`, if `name` ends with it.
+fn replace_suffix(name: &Name, from: &Name, to: &Name) -> Option<Name> {
+    if !name.is_subdomain_of(from) {
+        return None;
+    }
+    let prefix = &name.labels()[..name.labels().len() - from.labels().len()];
+    Name::from_labels(prefix.iter().chain(to.labels()).cloned()).ok()
+}
+
+/// Rewrites the names of questions before passing them to the inner resolver.
+///
+/// Each rule replaces a domain suffix, e.g. with a rule from `svc.example` to `svc.cluster.local`,
+/// `db.svc.example` is resolved as `db.svc.cluster.local`. The first matching rule is applied. The owner
+/// names of the answers and the targets of CNAMEs among them are rewritten back, so that the client gets
+/// a connected chain of answers for the name it asked for.
+pub struct RewriteResolver<R> {
+    inner: R,
+    rules: Vec<(Name, Name)>,
+}
+
+impl<R: Resolver> RewriteResolver<R> {
+    pub fn new(inner: R, rules: Vec<(Name, Name)>) -> Self {
+        Self { inner, rules }
+    }
+}
+
+impl<R: Resolver> Resolver for RewriteResolver<R> {
+    fn resolve(&self, question: &Question, request: &Request) -> Result<Response> {
+        let rewritten = self
+            .rules
+            .iter()
+            .find_map(|(from, to)| Some((from, to, replace_suffix(&question.qname, from, to)?)));
+        let Some((from, to, qname)) = rewritten else {
+            return self.inner.resolve(question, request);
+        };
+
+        let mut response = self.inner.resolve(&Question { qname, ..question.clone() }, request)?;
+        for record in &mut response.answers {
+            if let Some(name) = replace_suffix(&record.name, to, from) {
+                record.name = name;
+            }
+            // CNAME targets are owner names of later answers, so they must change the same way to keep the chain linked.
+            if let RData::CNAME(target) = &mut record.rdata {
+                if let Some(name) = replace_suffix(target, to, from) {
+                    *target = name;
+                }
+            }
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::message::{Answer, Message, QClass, QType, RCode};
+    use crate::resolver::Transport;
+
+    /// Notes the names asked for and answers `db.svc.cluster.local` through a chain of CNAMEs.
+    #[derive(Default)]
+    struct Stub {
+        asked: Mutex<Vec<Name>>,
+    }
+
+    impl Resolver for Stub {
+        fn resolve(&self, question: &Question, _request: &Request) -> Result<Response> {
+            self.asked.lock().unwrap().push(question.qname.clone());
+            let record = |owner: &str, rtype, rdata| Answer { name: name(owner), rtype, rclass: QClass::IN, ttl: 60, rdata };
+            if question.qname != name("db.svc.cluster.local") {
+                return Ok(Response::new(RCode::NameError));
+            }
+            Ok(Response::from_answers(vec![
+                record("db.svc.cluster.local", QType::CNAME, RData::CNAME(name("primary.svc.cluster.local"))),
+                record("primary.svc.cluster.local", QType::CNAME, RData::CNAME(name("host.example.net"))),
+                record("host.example.net", QType::A, RData::A(Ipv4Addr::new(192, 0, 2, 1))),
+            ]))
+        }
+    }
+
+    fn name(text: &str) -> Name {
+        text.parse().unwrap()
+    }
+
+    fn resolve(resolver: &RewriteResolver<Stub>, qname: &str) -> Response {
+        let question = Question { qname: name(qname), qtype: QType::A, qclass: QClass::IN };
+        let request = Request {
+            client: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            transport: Transport::Udp,
+            message: Message::query(0, question.clone(), true),
+        };
+        resolver.resolve(&question, &request).unwrap()
+    }
+
+    fn resolver() -> RewriteResolver<Stub> {
+        let rules = vec![(name("svc.example"), name("svc.cluster.local")), (name("example"), name("other.test"))];
+        RewriteResolver::new(Stub::default(), rules)
+    }
+
+    #[test]
+    fn rewrites_the_question_with_the_first_matching_rule() {
+        let resolver = resolver();
+        resolve(&resolver, "DB.svc.example");
+        resolve(&resolver, "www.example");
+        resolve(&resolver, "www.example.com");
+        assert_eq!(*resolver.inner.asked.lock().unwrap(), [name("db.svc.cluster.local"), name("www.other.test"), name("www.example.com")]);
+    }
+
+    #[test]
+    fn rewrites_owners_and_cname_targets_back() {
+        let response = resolve(&resolver(), "db.svc.example");
+        let chain: Vec<_> = response.answers.iter().map(|record| (record.name.clone(), record.rdata.clone())).collect();
+        assert_eq!(
+            chain,
+            [
+                (name("db.svc.example"), RData::CNAME(name("primary.svc.example"))),
+                // Names outside the rewritten domain are left alone.
+                (name("primary.svc.example"), RData::CNAME(name("host.example.net"))),
+                (name("host.example.net"), RData::A(Ipv4Addr::new(192, 0, 2, 1))),
+            ]
+        );
+    }
+}
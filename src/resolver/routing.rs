@@ -0,0 +1,51 @@
+use anyhow::Result;
+
+use crate::message::{Name, Question, RCode};
+
+use super::{Request, Resolver, Response};
+
+/// Sends each question to the resolver routed for the longest matching domain suffix of its name.
+///
+/// For example with routes for `corp.example` and `example`, `www.corp.example` is resolved by the first one
+/// and `www.example` by the second. Questions matching no route go to the default route;
+/// without one they are refused.
+#[derive(Default)]
+pub struct RoutingResolver {
+    routes: Vec<(Name, Box<dyn Resolver>)>,
+    default: Option<Box<dyn Resolver>>,
+}
+
+impl RoutingResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Routes questions for `suffix` and all names below it to `resolver`, replacing any previous route for it.
+    pub fn add_route(&mut self, suffix: Name, resolver: Box<dyn Resolver>) {
+        self.routes.retain(|(existing, _)| *existing != suffix);
+        self.routes.push((suffix, resolver));
+    }
+
+    /// Routes questions matching no suffix to `resolver`.
+    pub fn set_default(&mut self, resolver: Box<dyn Resolver>) {
+        self.default = Some(resolver);
+    }
+
+    fn route(&self, name: &Name) -> Option<&dyn Resolver> {
+        self.routes
+            .iter()
+            .filter(|(suffix, _)| name.is_subdomain_of(suffix))
+            .max_by_key(|(suffix, _)| suffix.labels().len())
+            .map(|(_, resolver)| resolver.as_ref())
+            .or(self.default.as_deref())
+    }
+}
+
+impl Resolver for RoutingResolver {
+    fn resolve(&self, question: &Question, request: &Request) -> Result<Response> {
+        match self.route(&question.qname) {
+            Some(resolver) => resolver.resolve(question, request),
+            None => Ok(Response::new(RCode::Refused)),
+        }
+    }
+}
@@ -0,0 +1,426 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::message::{Message, RCode};
+
+/// Failure to obtain a response from an upstream server.
+#[derive(Debug, Error)]
+pub enum UpstreamError {
+    /// Every attempt timed out.
+    #[error("no response from {server} after {attempts} attempts")]
+    Timeout { server: SocketAddr, attempts: u32 },
+    /// The overall deadline of the client query passed.
+    #[error("deadline exceeded while querying {server}")]
+    DeadlineExceeded { server: SocketAddr },
+    /// The query could not be sent or the response could not be received.
+    #[error("failed to communicate with {server}")]
+    Io {
+        server: SocketAddr,
+        #[source]
+        source: io::Error,
+    },
+}
+
+/// How long to wait for upstream servers and how often to ask them again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How long to wait for a response to the first attempt.
+    pub attempt_timeout: Duration,
+    /// How many times a query is sent again after an attempt timed out.
+    pub retries: u32,
+    /// Factor by which the attempt timeout grows after each attempt.
+    pub backoff: u32,
+    /// Upper bound on the time spent on a single client query, across all attempts and servers.
+    pub deadline: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempt_timeout: Duration::from_secs(1),
+            retries: 2,
+            backoff: 2,
+            deadline: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Binds an ephemeral UDP socket of the same address family as `server` and connects it there.
+pub(crate) fn connect_udp(server: SocketAddr) -> io::Result<UdpSocket> {
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(server)?;
+    Ok(socket)
+}
+
+/// Sends `query` to `server` over UDP and waits for the matching response, retrying as configured by `policy`.
+/// No attempt is made after `deadline`.
+///
+/// Each query is sent from a fresh socket with an ephemeral port, connected to `server`,
+/// so the kernel drops datagrams from any other source. Datagrams which cannot be parsed
+/// or do not match the ID and question of `query` are discarded, as they may be spoofed;
+/// server errors without a question section are accepted.
+/// A late response to an earlier attempt is accepted.
+///
+/// Servers which do not implement EDNS may answer FORMERR to the OPT record (RFC 6891 section 7);
+/// the query is then sent again without it.
+pub(crate) fn query_udp(
+    server: SocketAddr,
+    query: &Message,
+    policy: &RetryPolicy,
+    deadline: Instant,
+) -> Result<Message, UpstreamError> {
+    let response = exchange_udp(server, query, policy, deadline)?;
+    if response.header.rcode != RCode::FormatError || query.edns.is_none() {
+        return Ok(response);
+    }
+    let plain = Message {
+        header: query.header.clone(),
+        questions: query.questions.clone(),
+        answers: Vec::new(),
+        authorities: Vec::new(),
+        additionals: Vec::new(),
+        edns: None,
+    };
+    exchange_udp(server, &plain, policy, deadline)
+}
+
+fn exchange_udp(
+    server: SocketAddr,
+    query: &Message,
+    policy: &RetryPolicy,
+    deadline: Instant,
+) -> Result<Message, UpstreamError> {
+    let io_error = |source| UpstreamError::Io { server, source };
+    let socket = connect_udp(server).map_err(io_error)?;
+    let bytes = query.as_bytes();
+
+    let mut attempt_timeout = policy.attempt_timeout;
+    for _ in 0..=policy.retries {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(UpstreamError::DeadlineExceeded { server });
+        }
+        socket.send(&bytes).map_err(io_error)?;
+        if let Some(response) = receive(&socket, query, deadline.min(now + attempt_timeout)).map_err(io_error)? {
+            return Ok(response);
+        }
+        attempt_timeout *= policy.backoff;
+    }
+    Err(UpstreamError::Timeout { server, attempts: policy.retries + 1 })
+}
+
+/// Waits until `until` for a response to `query`, returning `None` if none arrived.
+fn receive(socket: &UdpSocket, query: &Message, until: Instant) -> io::Result<Option<Message>> {
+    let mut buf = vec![0; u16::MAX as usize];
+    loop {
+        let remaining = until.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let size = match socket.recv(&mut buf) {
+            Ok(size) => size,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(err) => return Err(err),
+        };
+        match Message::from_bytes(&buf[0..size]) {
+            Ok(response) if is_response_to(&response, query) => return Ok(Some(response)),
+            _ => continue,
+        }
+    }
+}
+
+/// Connects to `server` over TCP, giving up at `deadline`.
+pub(crate) fn connect_tcp(server: SocketAddr, deadline: Instant) -> Result<TcpStream, UpstreamError> {
+    let timeout = deadline.saturating_duration_since(Instant::now());
+    if timeout.is_zero() {
+        return Err(UpstreamError::DeadlineExceeded { server });
+    }
+    let stream = TcpStream::connect_timeout(&server, timeout).map_err(|source| match source.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => UpstreamError::DeadlineExceeded { server },
+        _ => UpstreamError::Io { server, source },
+    })?;
+    stream.set_nodelay(true).map_err(|source| UpstreamError::Io { server, source })?;
+    Ok(stream)
+}
+
+/// Sends `query` over an established TCP connection to `server` and waits until `deadline` for the matching response.
+///
+/// Messages which do not match the ID and question of `query` are skipped,
+/// e.g. late responses to earlier queries on a reused connection.
+pub(crate) fn query_tcp(
+    stream: &mut TcpStream,
+    server: SocketAddr,
+    query: &Message,
+    deadline: Instant,
+) -> Result<Message, UpstreamError> {
+    let io_error = |source: io::Error| match source.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => UpstreamError::DeadlineExceeded { server },
+        _ => UpstreamError::Io { server, source },
+    };
+
+    let bytes = query.as_bytes();
+    let mut framed = Vec::with_capacity(bytes.len() + 2);
+    framed.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    framed.extend(bytes);
+    stream.write_all(&framed).map_err(io_error)?;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(UpstreamError::DeadlineExceeded { server });
+        }
+        stream.set_read_timeout(Some(remaining)).map_err(io_error)?;
+
+        let mut len = [0; 2];
+        stream.read_exact(&mut len).map_err(io_error)?;
+        let mut buf = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut buf).map_err(io_error)?;
+        match Message::from_bytes(&buf) {
+            Ok(response) if is_response_to(&response, query) => return Ok(response),
+            _ => continue,
+        }
+    }
+}
+
+/// Whether `response` answers `query`: the ID must match, and so must the question, except for server errors.
+///
+/// Responses to queries the server could not or would not process may come without a question section,
+/// e.g. FORMERR to a query the server could not parse. Answers about the name in question, including
+/// NXDOMAIN, must always repeat the question.
+fn is_response_to(response: &Message, query: &Message) -> bool {
+    let questionless = matches!(
+        response.header.rcode,
+        RCode::FormatError | RCode::ServerFailure | RCode::NotImplemented | RCode::Refused
+    );
+    let questions_match = response.questions == query.questions || (response.questions.is_empty() && questionless);
+    response.header.is_reply && response.header.id == query.header.id && questions_match
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::message::{Answer, QClass, QType, Question, RData};
+
+    /// Starts a stand-in upstream on a loopback port, which answers each UDP query with the messages
+    /// `respond` returns for it. Returns its address and the number of queries it received so far.
+    pub(in crate::resolver) fn udp_upstream(
+        respond: impl Fn(&Message) -> Vec<Message> + Send + 'static,
+    ) -> (SocketAddr, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&queries);
+        thread::spawn(move || {
+            let mut buf = vec![0; u16::MAX as usize];
+            while let Ok((size, source)) = socket.recv_from(&mut buf) {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let query = Message::from_bytes(&buf[..size]).unwrap();
+                for response in respond(&query) {
+                    socket.send_to(&response.as_bytes(), source).unwrap();
+                }
+            }
+        });
+        (addr, queries)
+    }
+
+    /// Starts a stand-in upstream listening for TCP connections on `addr`, which answers each query with
+    /// the messages `respond` returns for it. Returns its address and the number of connections it accepted so far.
+    pub(in crate::resolver) fn tcp_upstream(
+        addr: SocketAddr,
+        respond: impl Fn(&Message) -> Vec<Message> + Send + Sync + 'static,
+    ) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind(addr).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&connections);
+        let respond = Arc::new(respond);
+        thread::spawn(move || {
+            for mut stream in listener.incoming().map_while(Result::ok) {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let respond = Arc::clone(&respond);
+                thread::spawn(move || loop {
+                    let mut len = [0; 2];
+                    if stream.read_exact(&mut len).is_err() {
+                        return;
+                    }
+                    let mut buf = vec![0; u16::from_be_bytes(len) as usize];
+                    stream.read_exact(&mut buf).unwrap();
+                    for response in respond(&Message::from_bytes(&buf).unwrap()) {
+                        let bytes = response.as_bytes();
+                        stream.write_all(&(bytes.len() as u16).to_be_bytes()).unwrap();
+                        stream.write_all(&bytes).unwrap();
+                    }
+                });
+            }
+        });
+        (addr, connections)
+    }
+
+    /// The reply to `query` with `rcode` and `answers`.
+    pub(in crate::resolver) fn reply_to(query: &Message, rcode: RCode, answers: Vec<Answer>) -> Message {
+        let query = Message {
+            header: query.header.clone(),
+            questions: query.questions.clone(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
+        };
+        query.reply(rcode, answers)
+    }
+
+    /// The reply to `query` with a single A record for the name in question.
+    pub(in crate::resolver) fn answer(query: &Message, ip: Ipv4Addr) -> Message {
+        let question = &query.questions[0];
+        let record = Answer { name: question.qname.clone(), rtype: QType::A, rclass: QClass::IN, ttl: 300, rdata: RData::A(ip) };
+        reply_to(query, RCode::NoError, vec![record])
+    }
+
+    fn query() -> Message {
+        let question = Question { qname: "www.example.com".parse().unwrap(), qtype: QType::A, qclass: QClass::IN };
+        Message::query(0x1234, question, true)
+    }
+
+    fn reply(rcode: RCode) -> Message {
+        query().reply(rcode, Vec::new())
+    }
+
+    fn policy(attempt_millis: u64, retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            attempt_timeout: Duration::from_millis(attempt_millis),
+            retries,
+            backoff: 2,
+            deadline: Duration::from_secs(5),
+        }
+    }
+
+    fn deadline() -> Instant {
+        Instant::now() + Duration::from_secs(5)
+    }
+
+    #[test]
+    fn matches_id_and_question() {
+        let query = query();
+        assert!(is_response_to(&reply(RCode::NoError), &query));
+        assert!(!is_response_to(&query, &query), "a query is not a response");
+
+        let mut other_id = reply(RCode::NoError);
+        other_id.header.id += 1;
+        assert!(!is_response_to(&other_id, &query));
+
+        for change in [
+            |question: &mut Question| question.qname = "www.example.net".parse().unwrap(),
+            |question: &mut Question| question.qtype = QType::AAAA,
+            |question: &mut Question| question.qclass = QClass::CH,
+        ] {
+            let mut other_question = reply(RCode::NoError);
+            change(&mut other_question.questions[0]);
+            assert!(!is_response_to(&other_question, &query));
+        }
+    }
+
+    #[test]
+    fn accepts_server_errors_without_question() {
+        let query = query();
+        for rcode in [RCode::FormatError, RCode::ServerFailure, RCode::NotImplemented, RCode::Refused] {
+            let mut response = reply(rcode);
+            response.questions.clear();
+            assert!(is_response_to(&response, &query), "{rcode:?}");
+        }
+        for rcode in [RCode::NoError, RCode::NameError] {
+            let mut response = reply(rcode);
+            response.questions.clear();
+            assert!(!is_response_to(&response, &query), "{rcode:?}");
+        }
+    }
+
+    #[test]
+    fn discards_spoofed_responses() {
+        let (addr, _) = udp_upstream(|query| {
+            let mut other_id = answer(query, Ipv4Addr::new(192, 0, 2, 66));
+            other_id.header.id = other_id.header.id.wrapping_add(1);
+            let mut other_question = answer(query, Ipv4Addr::new(192, 0, 2, 66));
+            other_question.questions[0].qname = "evil.example.com".parse().unwrap();
+            let mut nxdomain = reply_to(query, RCode::NameError, Vec::new());
+            nxdomain.questions.clear();
+            vec![other_id, other_question, nxdomain, answer(query, Ipv4Addr::new(192, 0, 2, 1))]
+        });
+
+        let response = query_udp(addr, &query(), &policy(1000, 0), deadline()).unwrap();
+        assert_eq!(response.answers[0].rdata, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+    }
+
+    #[test]
+    fn retries_formerr_without_edns() {
+        let (addr, queries) = udp_upstream(|query| match query.edns {
+            Some(_) => vec![reply_to(query, RCode::FormatError, Vec::new())],
+            None => vec![answer(query, Ipv4Addr::new(192, 0, 2, 1))],
+        });
+
+        let response = query_udp(addr, &query(), &policy(1000, 0), deadline()).unwrap();
+        assert_eq!(response.header.rcode, RCode::NoError);
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn retries_with_backoff() {
+        let (addr, queries) = udp_upstream(|_| Vec::new());
+        let start = Instant::now();
+        let err = query_udp(addr, &query(), &policy(50, 2), deadline()).unwrap_err();
+
+        assert!(matches!(err, UpstreamError::Timeout { attempts: 3, .. }), "{err:?}");
+        assert_eq!(queries.load(Ordering::SeqCst), 3);
+        // 50ms, then 100ms, then 200ms.
+        assert!(start.elapsed() >= Duration::from_millis(350));
+    }
+
+    #[test]
+    fn stops_at_the_deadline() {
+        let (addr, queries) = udp_upstream(|_| Vec::new());
+        let start = Instant::now();
+        let err = query_udp(addr, &query(), &policy(50, 5), start + Duration::from_millis(120)).unwrap_err();
+
+        assert!(matches!(err, UpstreamError::DeadlineExceeded { .. }), "{err:?}");
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+        assert!(start.elapsed() < Duration::from_millis(300));
+    }
+
+    #[test]
+    fn accepts_late_responses_to_earlier_attempts() {
+        let (addr, queries) = udp_upstream(|query| {
+            thread::sleep(Duration::from_millis(80));
+            vec![answer(query, Ipv4Addr::new(192, 0, 2, 1))]
+        });
+
+        let response = query_udp(addr, &query(), &policy(50, 2), deadline()).unwrap();
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn skips_mismatched_messages_over_tcp() {
+        let (addr, _) = tcp_upstream((Ipv4Addr::LOCALHOST, 0).into(), |query| {
+            let mut stale = answer(query, Ipv4Addr::new(192, 0, 2, 66));
+            stale.header.id = stale.header.id.wrapping_sub(1);
+            vec![stale, answer(query, Ipv4Addr::new(192, 0, 2, 1))]
+        });
+
+        let mut stream = connect_tcp(addr, deadline()).unwrap();
+        for _ in 0..2 {
+            let response = query_tcp(&mut stream, addr, &query(), deadline()).unwrap();
+            assert_eq!(response.answers[0].rdata, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::message::{Edns, Header, Message, Opcode, RCode, DEFAULT_UDP_PAYLOAD, MIN_UDP_PAYLOAD};
+use crate::resolver::{Request, Resolver, Response, Transport};
+
+mod pool;
+mod tcp;
+mod udp;
+
+/// How long an idle TCP connection is kept open (RFC 7766 section 6.2.3 suggests the order of seconds).
+pub const DEFAULT_TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of threads answering the requests of a UDP socket or TCP listener.
+pub const DEFAULT_WORKERS: usize = 32;
+
+/// Number of requests waiting for a worker, beyond which further UDP requests are dropped
+/// and TCP requests are answered by the thread reading them.
+pub const DEFAULT_QUEUE_SIZE: usize = 1024;
+
+/// Number of TCP connections served at the same time, beyond which further connections are closed right away.
+pub const DEFAULT_MAX_TCP_CONNECTIONS: usize = 256;
+
+/// Answers DNS requests received over UDP and TCP using a single [`Resolver`].
+///
+/// Requests are answered concurrently by pools of worker threads, one per UDP socket and TCP listener; each TCP
+/// connection is read by a thread of its own. A request waiting for a slow upstream thus does not hold up any other,
+/// not even later requests pipelined on the same TCP connection.
+pub struct Server {
+    resolver: Arc<dyn Resolver>,
+    /// The largest UDP payload this server sends and advertises in its OPT records.
+    pub max_udp_payload: u16,
+    /// How long a TCP connection may stay idle before it is closed.
+    pub tcp_idle_timeout: Duration,
+    /// Number of threads answering requests, per UDP socket and per TCP listener.
+    pub workers: usize,
+    /// Number of received requests waiting for a worker, beyond which UDP requests are dropped.
+    pub queue_size: usize,
+    /// Maximum number of TCP connections served at the same time.
+    pub max_tcp_connections: usize,
+}
+
+/// A reply to a single request.
+struct Reply {
+    message: Message,
+    /// The largest UDP payload the requestor is able to receive.
+    max_udp_payload: u16,
+}
+
+impl Server {
+    pub fn new(resolver: Arc<dyn Resolver>) -> Self {
+        Self {
+            resolver,
+            max_udp_payload: DEFAULT_UDP_PAYLOAD,
+            tcp_idle_timeout: DEFAULT_TCP_IDLE_TIMEOUT,
+            workers: DEFAULT_WORKERS,
+            queue_size: DEFAULT_QUEUE_SIZE,
+            max_tcp_connections: DEFAULT_MAX_TCP_CONNECTIONS,
+        }
+    }
+
+    /// Computes the reply to the raw request `msg` received from `source` over `transport`.
+    ///
+    /// Returns `None` for messages which cannot be answered at all, i.e. when not even the header can be parsed.
+    fn handle(&self, msg: &[u8], source: SocketAddr, transport: Transport) -> Option<Reply> {
+        let message = match Message::from_bytes(msg) {
+            Ok(message) => message,
+            Err(err) => {
+                eprintln!("Malformed message from {source}: {err}");
+                return Header::from_bytes(msg).ok().map(|header| {
+                    let request = Message {
+                        header,
+                        questions: Vec::new(),
+                        answers: Vec::new(),
+                        authorities: Vec::new(),
+                        additionals: Vec::new(),
+                        edns: None,
+                    };
+                    Reply {
+                        message: request.reply(RCode::FormatError, Vec::new()),
+                        max_udp_payload: MIN_UDP_PAYLOAD,
+                    }
+                });
+            }
+        };
+
+        let request = Request { client: source, transport, message };
+        let edns = request.edns().cloned();
+        let mut message = match request.message.header.opcode {
+            _ if edns.as_ref().is_some_and(|edns| edns.version != 0) => request.message.reply(RCode::BadVers, Vec::new()),
+            Opcode::Query => {
+                // The flags are only set if every question was answered with them.
+                let mut response = Response::new(RCode::NoError);
+                response.authoritative = !request.message.questions.is_empty();
+                response.recursion_available = !request.message.questions.is_empty();
+                for question in &request.message.questions {
+                    match self.resolver.resolve(question, &request) {
+                        Ok(partial) => {
+                            if response.rcode == RCode::NoError {
+                                response.rcode = partial.rcode;
+                            }
+                            response.authoritative &= partial.authoritative;
+                            response.recursion_available &= partial.recursion_available;
+                            response.answers.extend(partial.answers);
+                            response.authorities.extend(partial.authorities);
+                            response.additionals.extend(partial.additionals);
+                        }
+                        Err(err) => {
+                            eprintln!("Failed to resolve {} for {source}: {err:#}", question.qname);
+                            response = Response::new(RCode::ServerFailure);
+                            break;
+                        }
+                    }
+                }
+                let mut reply = request.message.reply(response.rcode, response.answers);
+                reply.header.authoritative = response.authoritative;
+                reply.header.recursion_available = response.recursion_available;
+                reply.authorities = response.authorities;
+                reply.additionals = response.additionals;
+                reply
+            }
+            Opcode::IQuery | Opcode::Status | Opcode::Reserved(_) => {
+                request.message.reply(RCode::NotImplemented, Vec::new())
+            }
+        };
+        let max_udp_payload = edns.as_ref().map_or(MIN_UDP_PAYLOAD, |edns| edns.max_payload().min(self.max_udp_payload));
+        message.edns = edns.map(|edns| Edns {
+            dnssec_ok: edns.dnssec_ok,
+            ..Edns::new(self.max_udp_payload)
+        });
+
+        Some(Reply { message, max_udp_payload })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::resolver::DummyResolver;
+
+    /// A query for the A records of `www.example.com`.
+    const QUERY: &[u8] = b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x03www\x07example\x03com\x00\x00\x01\x00\x01";
+
+    fn server() -> Server {
+        let mut server = Server::new(Arc::new(DummyResolver));
+        server.max_udp_payload = 1232;
+        server
+    }
+
+    /// Handles [`QUERY`] with `edns`, or without EDNS if `None`.
+    fn handle(server: &Server, edns: Option<Edns>) -> Reply {
+        let mut query = Message::from_bytes(QUERY).unwrap();
+        query.edns = edns;
+        server.handle(&query.as_bytes(), SocketAddr::from((Ipv4Addr::LOCALHOST, 53000)), Transport::Udp).unwrap()
+    }
+
+    #[test]
+    fn answers_unknown_edns_versions_with_badvers() {
+        let reply = handle(&server(), Some(Edns { version: 1, ..Edns::new(4096) }));
+        assert_eq!(reply.message.header.rcode, RCode::BadVers);
+        assert!(reply.message.answers.is_empty());
+        // The extended RCODE needs an OPT record, which advertises the version this server supports.
+        let edns = Message::from_bytes(&reply.message.as_bytes()).unwrap().edns.unwrap();
+        assert_eq!(edns.version, 0);
+    }
+
+    #[test]
+    fn limits_replies_to_the_advertised_payload() {
+        let server = server();
+        assert_eq!(handle(&server, Some(Edns::new(800))).max_udp_payload, 800);
+        // Less than 512 octets is treated as 512, more than this server sends is capped.
+        assert_eq!(handle(&server, Some(Edns::new(100))).max_udp_payload, 512);
+        assert_eq!(handle(&server, Some(Edns::new(4096))).max_udp_payload, 1232);
+
+        let reply = handle(&server, Some(Edns::new(100)));
+        assert_eq!(reply.message.header.rcode, RCode::NoError);
+        assert_eq!(reply.message.edns.unwrap().udp_payload_size, 1232);
+    }
+
+    #[test]
+    fn answers_without_edns_in_512_octets() {
+        let reply = handle(&server(), None);
+        assert_eq!(reply.max_udp_payload, 512);
+        assert!(reply.message.edns.is_none());
+        assert_eq!(reply.message.answers.len(), 1);
+    }
+}
@@ -0,0 +1,54 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed number of worker threads running jobs from a bounded queue.
+pub(super) struct ThreadPool {
+    sender: SyncSender<Job>,
+}
+
+impl ThreadPool {
+    /// Starts `workers` threads sharing a queue of up to `queue_size` pending jobs.
+    pub(super) fn new(workers: usize, queue_size: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..workers.max(1) {
+            let receiver = Arc::clone(&receiver);
+            thread::spawn(move || Self::work(&receiver));
+        }
+        Self { sender }
+    }
+
+    fn work(receiver: &Mutex<Receiver<Job>>) {
+        loop {
+            // The lock is released before running the job, so that other workers can pick up the next one.
+            let job = receiver.lock().unwrap().recv();
+            match job {
+                // A panicking job has already been reported by the panic hook, the worker carries on.
+                Ok(job) => {
+                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                }
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Queues `job` to be run by the next idle worker.
+    ///
+    /// Returns `false` without running the job if the queue is full.
+    pub(super) fn try_execute(&self, job: impl FnOnce() + Send + 'static) -> bool {
+        self.sender.try_send(Box::new(job)).is_ok()
+    }
+
+    /// Queues `job` to be run by the next idle worker, or runs it right away if the queue is full.
+    ///
+    /// For jobs which must not be dropped; the calling thread is held up instead.
+    pub(super) fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        if let Err(TrySendError::Full(job) | TrySendError::Disconnected(job)) = self.sender.try_send(Box::new(job)) {
+            job();
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{Context, Result};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::pool::ThreadPool;
+use super::Server;
+use crate::resolver::Transport;
+
+/// Counts an open connection until it is dropped, even if serving the connection panicked.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Server {
+    /// Accepts connections on `listener` and reads requests on each of them in a separate thread, forever.
+    /// The requests of all connections are answered by a pool of `workers` threads.
+    ///
+    /// Connections beyond `max_tcp_connections` are closed right away.
+    pub fn serve_tcp(self: Arc<Self>, listener: TcpListener) -> Result<()> {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let pool = Arc::new(ThreadPool::new(self.workers, self.queue_size));
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("Failed to accept TCP connection: {err}");
+                    continue;
+                }
+            };
+            if connections.fetch_add(1, Ordering::Relaxed) >= self.max_tcp_connections {
+                connections.fetch_sub(1, Ordering::Relaxed);
+                eprintln!("Closed TCP connection: {} connections are open already", self.max_tcp_connections);
+                continue;
+            }
+
+            let server = Arc::clone(&self);
+            let pool = Arc::clone(&pool);
+            let slot = ConnectionSlot(Arc::clone(&connections));
+            thread::spawn(move || {
+                let _slot = slot;
+                let Ok(peer) = stream.peer_addr() else {
+                    return;
+                };
+                if let Err(err) = server.serve_connection(stream, peer, &pool) {
+                    eprintln!("TCP connection from {peer} failed: {err:#}");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads length-prefixed requests (RFC 1035 section 4.2.2) until the client closes the connection or stays idle for too long.
+    ///
+    /// Clients may pipeline requests: each one is handed to `pool` as soon as it is read, and the replies are sent
+    /// as they become ready, possibly out of order (RFC 7766 section 6.2.1.1). Clients match them up by ID.
+    fn serve_connection(self: &Arc<Self>, stream: TcpStream, peer: SocketAddr, pool: &ThreadPool) -> Result<()> {
+        stream
+            .set_read_timeout(Some(self.tcp_idle_timeout))
+            .context("Failed to set idle timeout")?;
+        // A client which does not read its replies must not hold up the worker writing to it for good.
+        stream
+            .set_write_timeout(Some(self.tcp_idle_timeout))
+            .context("Failed to set write timeout")?;
+        stream.set_nodelay(true).context("Failed to disable Nagle's algorithm")?;
+        let writer = Arc::new(Mutex::new(stream.try_clone().context("Failed to clone connection")?));
+        let mut reader = stream;
+
+        loop {
+            let mut len = [0; 2];
+            match reader.read_exact(&mut len) {
+                Ok(()) => {}
+                Err(err) if matches!(err.kind(), ErrorKind::UnexpectedEof | ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(());
+                }
+                Err(err) => return Err(err).context("Failed to read message length"),
+            }
+            let mut msg = vec![0; u16::from_be_bytes(len) as usize];
+            reader.read_exact(&mut msg).context("Failed to read message")?;
+
+            let server = Arc::clone(self);
+            let writer = Arc::clone(&writer);
+            pool.execute(move || server.reply_tcp(&msg, peer, &writer));
+        }
+    }
+
+    /// Answers a single request of the connection to `peer`, closing the connection if that is not possible.
+    fn reply_tcp(&self, msg: &[u8], peer: SocketAddr, writer: &Mutex<TcpStream>) {
+        let Some(reply) = self.handle(msg, peer, Transport::Tcp) else {
+            let _ = writer.lock().unwrap().shutdown(Shutdown::Both);
+            return;
+        };
+
+        let bytes = reply.message.as_bytes_limited(u16::MAX as usize);
+        let mut framed = Vec::with_capacity(bytes.len() + 2);
+        framed.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+        framed.extend(bytes);
+        // Each reply is written as a whole, so that replies of concurrent workers do not interleave.
+        let mut stream = writer.lock().unwrap();
+        if let Err(err) = stream.write_all(&framed) {
+            eprintln!("Failed to send response to {peer}: {err}");
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::message::{Message, QClass, QType, Question, RCode};
+    use crate::resolver::{Request, Resolver, Response};
+
+    /// Takes a while to answer questions for `slow.`, and answers everything else right away.
+    struct Slow;
+
+    impl Resolver for Slow {
+        fn resolve(&self, question: &Question, _request: &Request) -> anyhow::Result<Response> {
+            if question.qname == "slow.".parse()? {
+                thread::sleep(Duration::from_millis(500));
+            }
+            Ok(Response::new(RCode::NoError))
+        }
+    }
+
+    fn send(stream: &mut TcpStream, id: u16, name: &str) {
+        let question = Question { qname: name.parse().unwrap(), qtype: QType::A, qclass: QClass::IN };
+        let bytes = Message::query(id, question, true).as_bytes();
+        stream.write_all(&(bytes.len() as u16).to_be_bytes()).unwrap();
+        stream.write_all(&bytes).unwrap();
+    }
+
+    fn receive(stream: &mut TcpStream) -> Message {
+        let mut len = [0; 2];
+        stream.read_exact(&mut len).unwrap();
+        let mut msg = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut msg).unwrap();
+        Message::from_bytes(&msg).unwrap()
+    }
+
+    #[test]
+    fn answers_pipelined_requests_out_of_order() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Arc::new(Server::new(Arc::new(Slow)));
+        thread::spawn(move || server.serve_tcp(listener));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let start = Instant::now();
+        send(&mut stream, 1, "slow.");
+        send(&mut stream, 2, "fast.");
+        send(&mut stream, 3, "fast.");
+
+        let mut ids: Vec<u16> = (0..3).map(|_| receive(&mut stream).header.id).collect();
+        // The fast requests are answered while the slow one is still being resolved, in either order.
+        assert_eq!(ids.pop(), Some(1));
+        ids.sort_unstable();
+        assert_eq!(ids, [2, 3]);
+        assert!(start.elapsed() < Duration::from_millis(1000));
+    }
+}
@@ -0,0 +1,45 @@
+use std::net::UdpSocket;
+use std::sync::Arc;
+
+use super::pool::ThreadPool;
+use super::Server;
+use crate::resolver::Transport;
+
+impl Server {
+    /// Answers requests arriving on `socket` using a pool of `workers` threads, forever.
+    ///
+    /// Failures are logged and affect only the request at hand, the socket keeps serving other clients.
+    /// When all workers are busy and `queue_size` requests are waiting, further requests are dropped;
+    /// clients will ask again.
+    pub fn serve_udp(self: Arc<Self>, socket: UdpSocket) -> ! {
+        let socket = Arc::new(socket);
+        let pool = ThreadPool::new(self.workers, self.queue_size);
+        let mut buf = vec![0; self.max_udp_payload as usize];
+
+        loop {
+            let (size, source) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(err) => {
+                    eprintln!("Failed to receive UDP message: {err}");
+                    continue;
+                }
+            };
+
+            let msg = buf[0..size].to_vec();
+            let server = Arc::clone(&self);
+            let reply_socket = Arc::clone(&socket);
+            let queued = pool.try_execute(move || {
+                let Some(reply) = server.handle(&msg, source, Transport::Udp) else {
+                    return;
+                };
+                let bytes = reply.message.as_bytes_limited(reply.max_udp_payload as usize);
+                if let Err(err) = reply_socket.send_to(&bytes, source) {
+                    eprintln!("Failed to send response to {source}: {err}");
+                }
+            });
+            if !queued {
+                eprintln!("Dropped request from {source}: all workers are busy");
+            }
+        }
+    }
+}
@@ -35,6 +35,15 @@ macro_rules! int_enum {
                     n => Self::Reserved(n),
                 }
             }
+            /// Looks up a variant by its name, ignoring case.
+            pub fn from_name(name: &str) -> Option<Self> {
+                $(
+                    if name.eq_ignore_ascii_case(stringify!($key)) {
+                        return Some(Self::$key);
+                    }
+                )+
+                None
+            }
         }
     };
 }
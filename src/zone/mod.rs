@@ -0,0 +1,135 @@
+//! Zones loaded from master files, as served by an authoritative name server.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::message::{Answer, Name, QType, RData};
+
+mod parser;
+
+use parser::Parser;
+
+/// Reasons why a zone could not be loaded.
+#[derive(Debug, Error)]
+pub enum ZoneError {
+    /// A master file (or a file it includes) could not be read.
+    #[error("failed to read {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// A line of a master file is malformed.
+    #[error("{}:{line}: {message}", path.display())]
+    Syntax { path: PathBuf, line: usize, message: String },
+    /// The records are well-formed, but do not make up a valid zone.
+    #[error("zone {origin}: {message}")]
+    Invalid { origin: Name, message: String },
+}
+
+/// The records of a zone, indexed by owner name.
+#[derive(Debug, Clone)]
+pub struct Zone {
+    origin: Name,
+    soa: Answer,
+    /// Records by owner name. Empty non-terminals, names that only exist because there are names below them,
+    /// are present with no records.
+    nodes: HashMap<Name, Vec<Answer>>,
+}
+
+impl Zone {
+    /// Loads the zone `origin` from the master file at `path` (RFC 1035 section 5).
+    ///
+    /// Supported are the `$ORIGIN`, `$TTL` and `$INCLUDE` directives and records of class IN
+    /// with the types A, AAAA, NS, CNAME, PTR, MX, SOA and TXT.
+    pub fn load(path: &Path, origin: &Name) -> Result<Self, ZoneError> {
+        let mut parser = Parser::new();
+        parser.parse_file(path, origin)?;
+        Self::from_records(origin.clone(), parser.into_records())
+    }
+
+    /// Parses the zone `origin` from the contents of a master file; `path` is used for errors and `$INCLUDE`s.
+    pub fn parse(text: &str, path: &Path, origin: &Name) -> Result<Self, ZoneError> {
+        let mut parser = Parser::new();
+        parser.parse_str(text, path, origin)?;
+        Self::from_records(origin.clone(), parser.into_records())
+    }
+
+    /// Builds a zone out of its records, which must contain exactly one SOA record, owned by `origin`.
+    pub fn from_records(origin: Name, records: Vec<Answer>) -> Result<Self, ZoneError> {
+        let invalid = |message: String| ZoneError::Invalid { origin: origin.clone(), message };
+
+        let mut soa = None;
+        let mut nodes: HashMap<Name, Vec<Answer>> = HashMap::new();
+        for record in records {
+            if !record.name.is_subdomain_of(&origin) {
+                return Err(invalid(format!("{} is outside of the zone", record.name)));
+            }
+            if record.rtype == QType::SOA {
+                if record.name != origin {
+                    return Err(invalid(format!("SOA record at {}, not at the zone apex", record.name)));
+                }
+                if soa.is_some() {
+                    return Err(invalid("more than one SOA record".into()));
+                }
+                soa = Some(record.clone());
+            }
+
+            // Register the empty non-terminals between the owner and the origin.
+            let labels = record.name.labels();
+            for skip in 1..=labels.len() - origin.labels().len() {
+                let ancestor = Name::from_labels(labels[skip..].iter().cloned()).expect("suffix of a valid name");
+                nodes.entry(ancestor).or_default();
+            }
+
+            let node = nodes.entry(record.name.clone()).or_default();
+            if !node.contains(&record) {
+                node.push(record);
+            }
+        }
+
+        for (name, records) in &nodes {
+            let has_cname = records.iter().any(|record| record.rtype == QType::CNAME);
+            if has_cname && records.len() > 1 {
+                return Err(invalid(format!("{name} has a CNAME record and other data")));
+            }
+        }
+
+        let soa = soa.ok_or_else(|| invalid("no SOA record".into()))?;
+        Ok(Self { origin, soa, nodes })
+    }
+
+    /// The name of the zone apex.
+    pub fn origin(&self) -> &Name {
+        &self.origin
+    }
+
+    /// The SOA record of the zone.
+    pub fn soa(&self) -> &Answer {
+        &self.soa
+    }
+
+    /// The SOA record with its TTL lowered to the minimum field, as used for negative answers (RFC 2308 section 3).
+    pub fn negative_soa(&self) -> Answer {
+        let mut soa = self.soa.clone();
+        if let RData::SOA { minimum, .. } = soa.rdata {
+            soa.ttl = soa.ttl.min(minimum as i32);
+        }
+        soa
+    }
+
+    /// The records owned by `name`, or `None` if the name does not exist in the zone.
+    ///
+    /// Empty non-terminals exist, but own no records.
+    pub fn records(&self, name: &Name) -> Option<&[Answer]> {
+        self.nodes.get(name).map(Vec::as_slice)
+    }
+
+    /// The records of type `rtype` owned by `name`.
+    pub fn rrset(&self, name: &Name, rtype: QType) -> impl Iterator<Item = &Answer> {
+        self.records(name).unwrap_or_default().iter().filter(move |record| record.rtype == rtype)
+    }
+}
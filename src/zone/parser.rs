@@ -0,0 +1,586 @@
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+use crate::message::{Answer, Name, QClass, QType, RData};
+
+use super::ZoneError;
+
+/// A whitespace separated token of a master file.
+#[derive(Debug)]
+struct Token {
+    /// The text of the token, with escape sequences kept as they are.
+    text: String,
+    /// Whether the token was enclosed in double quotes.
+    quoted: bool,
+}
+
+/// A logical line of a master file, i.e. a physical line with parenthesized continuations joined.
+#[derive(Debug)]
+struct Line {
+    /// Physical line number the logical line starts at.
+    number: usize,
+    /// Whether the line starts with whitespace, i.e. the owner of the previous record is reused.
+    blank_owner: bool,
+    tokens: Vec<Token>,
+}
+
+/// Parses master files (RFC 1035 section 5) into resource records.
+pub(super) struct Parser {
+    records: Vec<Answer>,
+    /// Nesting depth of `$INCLUDE` directives, to catch include loops.
+    depth: usize,
+}
+
+/// State which is local to a single file.
+struct FileState<'a> {
+    path: &'a Path,
+    origin: Name,
+    default_ttl: Option<i32>,
+    last_ttl: Option<i32>,
+    last_owner: Option<Name>,
+}
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+impl Parser {
+    pub(super) fn new() -> Self {
+        Self { records: Vec::new(), depth: 0 }
+    }
+
+    pub(super) fn into_records(self) -> Vec<Answer> {
+        self.records
+    }
+
+    /// Parses the file at `path`, with relative names completed by `origin`.
+    pub(super) fn parse_file(&mut self, path: &Path, origin: &Name) -> Result<(), ZoneError> {
+        self.parse_file_with_ttl(path, origin, None)
+    }
+
+    fn parse_file_with_ttl(&mut self, path: &Path, origin: &Name, default_ttl: Option<i32>) -> Result<(), ZoneError> {
+        let text = fs::read_to_string(path).map_err(|source| ZoneError::Io { path: path.to_owned(), source })?;
+        self.parse_text(&text, path, origin, default_ttl)
+    }
+
+    /// Parses `text`, which was read from `path` (used for errors and relative `$INCLUDE`s).
+    pub(super) fn parse_str(&mut self, text: &str, path: &Path, origin: &Name) -> Result<(), ZoneError> {
+        self.parse_text(text, path, origin, None)
+    }
+
+    fn parse_text(&mut self, text: &str, path: &Path, origin: &Name, default_ttl: Option<i32>) -> Result<(), ZoneError> {
+        let mut state = FileState {
+            path,
+            origin: origin.clone(),
+            default_ttl,
+            last_ttl: None,
+            last_owner: None,
+        };
+        for line in tokenize(text).map_err(|(number, message)| syntax(path, number, message))? {
+            self.parse_line(&mut state, line)?;
+        }
+        Ok(())
+    }
+
+    fn parse_line(&mut self, state: &mut FileState, line: Line) -> Result<(), ZoneError> {
+        let err = |message: String| syntax(state.path, line.number, message);
+        let mut tokens = line.tokens.iter().peekable();
+
+        if !line.blank_owner {
+            let first = tokens.peek().filter(|token| !token.quoted).map(|token| token.text.as_str());
+            match first {
+                Some(directive) if directive.eq_ignore_ascii_case("$ORIGIN") => {
+                    tokens.next();
+                    let origin = tokens.next().ok_or_else(|| err("$ORIGIN without a name".into()))?;
+                    state.origin = parse_name(&origin.text, &state.origin).map_err(err)?;
+                    return expect_end(tokens).map_err(err);
+                }
+                Some(directive) if directive.eq_ignore_ascii_case("$TTL") => {
+                    tokens.next();
+                    let ttl = tokens.next().ok_or_else(|| err("$TTL without a value".into()))?;
+                    state.default_ttl = Some(parse_ttl(&ttl.text).ok_or_else(|| err(format!("invalid TTL '{}'", ttl.text)))?);
+                    return expect_end(tokens).map_err(err);
+                }
+                Some(directive) if directive.eq_ignore_ascii_case("$INCLUDE") => {
+                    tokens.next();
+                    let file = tokens.next().ok_or_else(|| err("$INCLUDE without a file name".into()))?;
+                    let origin = match tokens.next() {
+                        Some(origin) => parse_name(&origin.text, &state.origin).map_err(err)?,
+                        None => state.origin.clone(),
+                    };
+                    expect_end(tokens).map_err(err)?;
+                    if self.depth >= MAX_INCLUDE_DEPTH {
+                        return Err(err("$INCLUDE nested too deeply".into()));
+                    }
+                    // The default TTL carries over into the included file, the origin does not carry back.
+                    let default_ttl = state.default_ttl.or(state.last_ttl);
+                    return self.include(state.path, &file.text, &origin, default_ttl);
+                }
+                Some(directive) if directive.starts_with('$') => {
+                    return Err(err(format!("unknown directive '{directive}'")));
+                }
+                _ => {}
+            }
+        }
+
+        let owner = if line.blank_owner {
+            state.last_owner.clone().ok_or_else(|| err("record without an owner".into()))?
+        } else {
+            let owner = tokens.next().ok_or_else(|| err("missing owner".into()))?;
+            parse_name(&owner.text, &state.origin).map_err(err)?
+        };
+
+        // TTL and class may come in either order, both are optional.
+        let mut ttl = None;
+        let mut class_seen = false;
+        let rtype = loop {
+            let token = tokens.next().ok_or_else(|| err("missing record type".into()))?;
+            if let Some(value) = parse_ttl(&token.text).filter(|_| ttl.is_none()) {
+                ttl = Some(value);
+            } else if !class_seen && QClass::from_name(&token.text).is_some() {
+                if !token.text.eq_ignore_ascii_case("IN") {
+                    return Err(err(format!("unsupported class '{}'", token.text)));
+                }
+                class_seen = true;
+            } else {
+                break QType::from_name(&token.text).ok_or_else(|| err(format!("unknown record type '{}'", token.text)))?;
+            }
+        };
+
+        let rdata: Vec<&Token> = tokens.collect();
+        let rdata = parse_rdata(rtype, &rdata, &state.origin).map_err(err)?;
+        let ttl = match ttl.or(state.default_ttl).or(state.last_ttl) {
+            Some(ttl) => ttl,
+            // RFC 2308 section 4: without any TTL, the SOA minimum is used.
+            None => match &rdata {
+                RData::SOA { minimum, .. } => *minimum as i32,
+                _ => return Err(err("no TTL given and no $TTL in effect".into())),
+            },
+        };
+
+        state.last_ttl = Some(ttl);
+        state.last_owner = Some(owner.clone());
+        self.records.push(Answer {
+            name: owner,
+            rtype,
+            rclass: QClass::IN,
+            ttl,
+            rdata,
+        });
+        Ok(())
+    }
+
+    /// Parses an included file; its path is relative to the directory of the including file.
+    fn include(&mut self, from: &Path, file: &str, origin: &Name, default_ttl: Option<i32>) -> Result<(), ZoneError> {
+        let path: PathBuf = match from.parent() {
+            Some(dir) => dir.join(file),
+            None => PathBuf::from(file),
+        };
+        self.depth += 1;
+        let result = self.parse_file_with_ttl(&path, origin, default_ttl);
+        self.depth -= 1;
+        result
+    }
+}
+
+fn syntax(path: &Path, line: usize, message: String) -> ZoneError {
+    ZoneError::Syntax { path: path.to_owned(), line, message }
+}
+
+fn expect_end<'a>(mut tokens: impl Iterator<Item = &'a Token>) -> Result<(), String> {
+    match tokens.next() {
+        Some(token) => Err(format!("unexpected '{}'", token.text)),
+        None => Ok(()),
+    }
+}
+
+/// Parses a domain name; `@` stands for the origin and names without a trailing dot are relative to it.
+fn parse_name(text: &str, origin: &Name) -> Result<Name, String> {
+    if text == "@" {
+        return Ok(origin.clone());
+    }
+    let name: Name = text.parse().map_err(|e| format!("invalid name '{text}': {e}"))?;
+    // A trailing dot makes the name absolute, unless it is escaped by an odd number of backslashes.
+    if let Some(rest) = text.strip_suffix('.') {
+        let backslashes = rest.len() - rest.trim_end_matches('\\').len();
+        if backslashes % 2 == 0 {
+            return Ok(name);
+        }
+    }
+    Name::from_labels(name.labels().iter().chain(origin.labels()).cloned()).map_err(|e| format!("invalid name '{text}': {e}"))
+}
+
+/// Parses a TTL, either as plain seconds or with BIND style units, e.g. `1h30m`.
+fn parse_ttl(text: &str) -> Option<i32> {
+    if !text.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut total: u64 = 0;
+    let mut number: Option<u64> = None;
+    for c in text.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            number = Some(number.unwrap_or(0).checked_mul(10)?.checked_add(digit as u64)?);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        total = total.checked_add(number.take()?.checked_mul(unit)?)?;
+    }
+    total = total.checked_add(number.unwrap_or(0))?;
+    i32::try_from(total).ok()
+}
+
+fn parse_u16(text: &str) -> Result<u16, String> {
+    text.parse().map_err(|_| format!("invalid number '{text}'"))
+}
+
+fn parse_u32(text: &str) -> Result<u32, String> {
+    parse_ttl(text).map(|n| n as u32).ok_or_else(|| format!("invalid number '{text}'"))
+}
+
+fn parse_rdata(rtype: QType, tokens: &[&Token], origin: &Name) -> Result<RData, String> {
+    let texts: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+    let expect = |count: usize| {
+        if texts.len() == count {
+            Ok(())
+        } else {
+            Err(format!("{rtype:?} record expects {count} fields, got {}", texts.len()))
+        }
+    };
+
+    Ok(match rtype {
+        QType::A => {
+            expect(1)?;
+            RData::A(texts[0].parse::<Ipv4Addr>().map_err(|_| format!("invalid IPv4 address '{}'", texts[0]))?)
+        }
+        QType::AAAA => {
+            expect(1)?;
+            RData::AAAA(texts[0].parse::<Ipv6Addr>().map_err(|_| format!("invalid IPv6 address '{}'", texts[0]))?)
+        }
+        QType::NS => {
+            expect(1)?;
+            RData::NS(parse_name(texts[0], origin)?)
+        }
+        QType::CNAME => {
+            expect(1)?;
+            RData::CNAME(parse_name(texts[0], origin)?)
+        }
+        QType::PTR => {
+            expect(1)?;
+            RData::PTR(parse_name(texts[0], origin)?)
+        }
+        QType::MX => {
+            expect(2)?;
+            RData::MX {
+                preference: parse_u16(texts[0])?,
+                exchange: parse_name(texts[1], origin)?,
+            }
+        }
+        QType::SOA => {
+            expect(7)?;
+            RData::SOA {
+                mname: parse_name(texts[0], origin)?,
+                rname: parse_name(texts[1], origin)?,
+                serial: texts[2].parse().map_err(|_| format!("invalid serial '{}'", texts[2]))?,
+                refresh: parse_u32(texts[3])?,
+                retry: parse_u32(texts[4])?,
+                expire: parse_u32(texts[5])?,
+                minimum: parse_u32(texts[6])?,
+            }
+        }
+        QType::TXT => {
+            if texts.is_empty() {
+                return Err("TXT record expects at least one string".into());
+            }
+            let strings = texts.iter().map(|text| unescape(text)).collect::<Result<Vec<_>, _>>()?;
+            if let Some(long) = strings.iter().find(|s| s.len() > 255) {
+                return Err(format!("character string of {} octets is longer than 255", long.len()));
+            }
+            RData::TXT(strings)
+        }
+        _ => return Err(format!("{rtype:?} records are not supported in master files")),
+    })
+}
+
+/// Resolves `\X` and `\DDD` escapes of a character string.
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        match bytes.get(i + 1..i + 4) {
+            Some(digits) if digits.iter().all(u8::is_ascii_digit) => {
+                let value = digits.iter().fold(0u16, |n, d| n * 10 + (d - b'0') as u16);
+                out.push(u8::try_from(value).map_err(|_| format!("invalid escape in '{text}'"))?);
+                i += 4;
+            }
+            _ => {
+                out.push(*bytes.get(i + 1).ok_or_else(|| format!("dangling escape in '{text}'"))?);
+                i += 2;
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Splits a master file into logical lines, removing comments and joining parenthesized continuations.
+///
+/// Errors carry the line number they occurred at.
+fn tokenize(text: &str) -> Result<Vec<Line>, (usize, String)> {
+    let bytes = text.as_bytes();
+    let mut lines = Vec::new();
+    let mut tokens = Vec::new();
+    let mut number = 1;
+    let mut start = 1;
+    let mut blank_owner = false;
+    let mut parens = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\n' => {
+                number += 1;
+                i += 1;
+                if parens == 0 {
+                    if !tokens.is_empty() {
+                        lines.push(Line { number: start, blank_owner, tokens: std::mem::take(&mut tokens) });
+                    }
+                    start = number;
+                    blank_owner = matches!(bytes.get(i), Some(b' ' | b'\t'));
+                }
+            }
+            b' ' | b'\t' | b'\r' => i += 1,
+            b';' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'(' => {
+                parens += 1;
+                i += 1;
+            }
+            b')' => {
+                if parens == 0 {
+                    return Err((number, "unbalanced ')'".into()));
+                }
+                parens -= 1;
+                i += 1;
+            }
+            b'"' => {
+                let begin = i + 1;
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    match bytes[i] {
+                        b'\n' => return Err((number, "unterminated string".into())),
+                        b'\\' => {
+                            // An escaped line break is part of the string, but still starts a new physical line.
+                            if bytes.get(i + 1) == Some(&b'\n') {
+                                number += 1;
+                            }
+                            i += 2;
+                        }
+                        _ => i += 1,
+                    }
+                }
+                if i >= bytes.len() {
+                    return Err((number, "unterminated string".into()));
+                }
+                tokens.push(Token { text: text[begin..i].to_owned(), quoted: true });
+                i += 1;
+            }
+            _ => {
+                let begin = i;
+                while i < bytes.len() && !matches!(bytes[i], b' ' | b'\t' | b'\r' | b'\n' | b';' | b'(' | b')' | b'"') {
+                    if bytes[i] == b'\\' && bytes.get(i + 1) == Some(&b'\n') {
+                        number += 1;
+                    }
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i = i.min(bytes.len());
+                tokens.push(Token { text: text[begin..i].to_owned(), quoted: false });
+            }
+        }
+    }
+    if parens != 0 {
+        return Err((number, "unbalanced '('".into()));
+    }
+    if !tokens.is_empty() {
+        lines.push(Line { number: start, blank_owner, tokens });
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn origin() -> Name {
+        "example.com.".parse().unwrap()
+    }
+
+    fn parse(lines: &[&str]) -> Result<Vec<Answer>, ZoneError> {
+        let mut parser = Parser::new();
+        parser.parse_str(&lines.join("\n"), Path::new("test.zone"), &origin())?;
+        Ok(parser.into_records())
+    }
+
+    fn error_line(lines: &[&str]) -> usize {
+        match parse(lines) {
+            Err(ZoneError::Syntax { line, .. }) => line,
+            other => panic!("expected a syntax error, got {other:?}"),
+        }
+    }
+
+    fn name(text: &str) -> Name {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn joins_parenthesized_lines_and_strips_comments() {
+        let records = parse(&[
+            "; The zone apex.",
+            "@ 3600 IN SOA ns admin ( ; primary and contact",
+            "    2024010101 ; serial",
+            "    1h 15m",
+            "    1w 300 )",
+            "www 300 IN TXT \"a;b\" ; not part of the string",
+        ])
+        .unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].rdata,
+            RData::SOA {
+                mname: name("ns.example.com"),
+                rname: name("admin.example.com"),
+                serial: 2024010101,
+                refresh: 3600,
+                retry: 900,
+                expire: 604800,
+                minimum: 300,
+            }
+        );
+        assert_eq!(records[1].rdata, RData::TXT(vec![b"a;b".to_vec()]));
+    }
+
+    #[test]
+    fn completes_relative_names() {
+        let records = parse(&[
+            "$TTL 300",
+            "@ NS ns1",
+            "@ NS ns2.example.net.",
+            "www CNAME @",
+            "$ORIGIN sub.example.com.",
+            "host A 192.0.2.1",
+            "$ORIGIN deeper",
+            "host A 192.0.2.2",
+        ])
+        .unwrap();
+
+        let names: Vec<String> = records.iter().map(|record| record.name.to_string()).collect();
+        assert_eq!(names, ["example.com", "example.com", "www.example.com", "host.sub.example.com", "host.deeper.sub.example.com"]);
+        assert_eq!(records[0].rdata, RData::NS(name("ns1.example.com")));
+        assert_eq!(records[1].rdata, RData::NS(name("ns2.example.net")));
+        assert_eq!(records[2].rdata, RData::CNAME(name("example.com")));
+    }
+
+    #[test]
+    fn reuses_owner_and_ttl() {
+        let records = parse(&[
+            "www 600 IN A 192.0.2.1",
+            "    IN AAAA 2001:db8::1",
+            "\tIN TXT hello",
+            "mail A 192.0.2.2",
+            "$TTL 60",
+            "ftp A 192.0.2.3",
+        ])
+        .unwrap();
+
+        let owners: Vec<String> = records.iter().map(|record| record.name.to_string()).collect();
+        assert_eq!(owners, ["www.example.com", "www.example.com", "www.example.com", "mail.example.com", "ftp.example.com"]);
+        let ttls: Vec<i32> = records.iter().map(|record| record.ttl).collect();
+        assert_eq!(ttls, [600, 600, 600, 600, 60]);
+    }
+
+    #[test]
+    fn resolves_escapes() {
+        let records = parse(&[
+            "$TTL 300",
+            "a\\.b A 192.0.2.1",
+            "\\065bc TXT \"say \\\"hi\\\"\" \\255\\000",
+            "dot\\. CNAME target\\.",
+        ])
+        .unwrap();
+
+        assert_eq!(records[0].name.labels()[0], b"a.b");
+        assert_eq!(records[0].name.labels().len(), 3);
+        assert_eq!(records[1].name, name("Abc.example.com"));
+        assert_eq!(records[1].rdata, RData::TXT(vec![b"say \"hi\"".to_vec(), vec![255, 0]]));
+        // An escaped dot does not make a name absolute.
+        assert_eq!(records[2].name.labels()[0], b"dot.");
+        assert_eq!(records[2].name.labels().len(), 3);
+        assert_eq!(records[2].rdata, RData::CNAME(Name::from_labels([&b"target."[..], b"example", b"com"]).unwrap()));
+    }
+
+    #[test]
+    fn reports_error_lines() {
+        assert_eq!(error_line(&["$TTL 300", "", "www A 192.0.2"]), 3);
+        assert_eq!(error_line(&["$TTL 300", "@ NS ns", "    CNAME"]), 3);
+        // Errors within parentheses are reported at the line the record starts at.
+        assert_eq!(error_line(&["$TTL 300", "@ SOA ns admin (", "  1 2 3 4 5", ")", "www A ::1"]), 5);
+        assert_eq!(error_line(&["$TTL 300", "www TXT \"line\\", "break\"", "www A ::1"]), 4);
+        assert_eq!(error_line(&["$TTL 300", "www TXT \"open", "www A 192.0.2.1"]), 2);
+        assert_eq!(error_line(&["$TTL 300", "@ NS ns )"]), 2);
+        assert_eq!(error_line(&["www A 192.0.2.1"]), 1);
+        assert_eq!(error_line(&["$TTL 300", "$GENERATE 1-2 host$ A 192.0.2.$"]), 2);
+    }
+
+    #[test]
+    fn scopes_included_files() {
+        let dir = env::temp_dir().join(format!("zone-parser-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/hosts.inc"), "$ORIGIN inner\nhost A 192.0.2.1\n$TTL 60\n").unwrap();
+        fs::write(dir.join("sub/broken.inc"), "\nhost A 192.0.2\n").unwrap();
+
+        let text = [
+            "$TTL 300",
+            "$INCLUDE sub/hosts.inc",
+            "$INCLUDE sub/hosts.inc other.example.com.",
+            "after A 192.0.2.2",
+        ]
+        .join("\n");
+        let mut parser = Parser::new();
+        parser.parse_str(&text, &dir.join("main.zone"), &origin()).unwrap();
+        let records = parser.into_records();
+
+        let names: Vec<String> = records.iter().map(|record| record.name.to_string()).collect();
+        // $ORIGIN and $TTL of the included files do not carry back, but the $TTL of the including file carries into them.
+        assert_eq!(names, ["host.inner.example.com", "host.inner.other.example.com", "after.example.com"]);
+        let ttls: Vec<i32> = records.iter().map(|record| record.ttl).collect();
+        assert_eq!(ttls, [300, 300, 300]);
+
+        let mut parser = Parser::new();
+        let err = parser.parse_str("$INCLUDE sub/broken.inc", &dir.join("main.zone"), &origin()).unwrap_err();
+        match err {
+            ZoneError::Syntax { path, line, .. } => {
+                assert_eq!(path, dir.join("sub/broken.inc"));
+                assert_eq!(line, 2);
+            }
+            other => panic!("expected a syntax error, got {other:?}"),
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}